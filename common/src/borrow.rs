@@ -1,10 +1,43 @@
-use near_sdk::{json_types::U128, near};
+use near_sdk::{
+    json_types::{U128, U64},
+    near,
+};
+
+use crate::rational::Rational;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 #[near(serializers = [borsh, json])]
 pub enum BorrowStatus {
     Healthy,
     Liquidation,
+    /// The position is healthy, but has been open for longer than the
+    /// market's maximum borrow duration, so it can be liquidated.
+    Expired,
+}
+
+/// Metrics of a borrow position at given prices, see
+/// [`crate::market::MarketConfiguration::borrow_position_details`].
+/// Ratios that do not fit in a `Rational<u128>` are approximated.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[near(serializers = [json])]
+pub struct BorrowPositionDetails {
+    pub status: BorrowStatus,
+    /// Value of the collateral divided by the value of the liability. `None`
+    /// if there is no liability.
+    pub collateral_ratio: Option<Rational<u128>>,
+    /// Collateral ratio divided by the minimum collateral ratio. The position
+    /// can be liquidated once this falls below 1.
+    pub health_factor: Option<Rational<u128>>,
+    /// Collateral asset price below which the position can be liquidated,
+    /// assuming the borrow asset price does not change. `None` if there is no
+    /// liability or no collateral.
+    pub liquidation_price: Option<Rational<u128>>,
+    /// Largest amount that can currently be borrowed. The origination fee is
+    /// added to the liability on top of this amount.
+    pub maximum_borrow_amount: U128,
+    /// Largest amount of collateral that can be withdrawn without making the
+    /// position unhealthy.
+    pub maximum_collateral_withdrawal: U128,
 }
 
 #[derive(Default)]
@@ -12,6 +45,14 @@ pub enum BorrowStatus {
 pub struct BorrowPosition {
     pub collateral_asset_deposit: U128,
     pub borrow_asset_liability: U128,
+    /// Interest has been added to `borrow_asset_liability` up until this
+    /// time.
+    pub last_accrued_at_block_timestamp_ms: U64,
+    /// The market's borrow index when interest was last added to
+    /// `borrow_asset_liability`. Zero if there has been no liability yet.
+    pub borrow_index: U128,
+    /// When the borrow asset liability last became non-zero.
+    pub started_at_block_timestamp_ms: Option<U64>,
 }
 
 impl BorrowPosition {
@@ -32,6 +73,7 @@ impl BorrowPosition {
     pub fn zero_out_borrow_asset_liability(&mut self) -> u128 {
         let value = self.borrow_asset_liability.0;
         self.borrow_asset_liability.0 = 0;
+        self.started_at_block_timestamp_ms = None;
         value
     }
 
@@ -52,6 +94,33 @@ impl BorrowPosition {
 
     pub fn decrease_borrow_asset_liability(&mut self, amount: u128) -> Option<U128> {
         self.borrow_asset_liability.0 = self.borrow_asset_liability.0.checked_sub(amount)?;
+        if self.borrow_asset_liability.0 == 0 {
+            self.started_at_block_timestamp_ms = None;
+        }
+        Some(self.borrow_asset_liability)
+    }
+
+    /// Records the start of the borrow if there is none yet.
+    pub fn start_borrow(&mut self, block_timestamp_ms: u64) {
+        self.started_at_block_timestamp_ms
+            .get_or_insert(block_timestamp_ms.into());
+    }
+
+    pub fn borrow_duration_ms(&self, block_timestamp_ms: u64) -> u64 {
+        self.started_at_block_timestamp_ms.map_or(0, |started_at| {
+            block_timestamp_ms.saturating_sub(started_at.0)
+        })
+    }
+
+    pub fn accrue_interest(
+        &mut self,
+        interest: u128,
+        borrow_index: u128,
+        block_timestamp_ms: u64,
+    ) -> Option<U128> {
+        self.increase_borrow_asset_liability(interest)?;
+        self.borrow_index.0 = borrow_index;
+        self.last_accrued_at_block_timestamp_ms.0 = block_timestamp_ms;
         Some(self.borrow_asset_liability)
     }
 }
@@ -12,6 +12,8 @@ pub enum Fee {
     Proportional(Rational<u16>),
 }
 
+pub const MS_PER_YEAR: u64 = 365 * 24 * 60 * 60 * 1000;
+
 impl Fee {
     pub fn of(&self, amount: u128) -> Option<u128> {
         match self {
@@ -21,8 +23,36 @@ impl Fee {
             }
         }
     }
+
+    /// Proportional fees must not exceed 100%.
+    pub fn is_valid(&self) -> bool {
+        match self {
+            Fee::Flat(_) => true,
+            Fee::Proportional(rational) => {
+                rational.denominator() != 0 && rational.numerator() <= rational.denominator()
+            }
+        }
+    }
+
+    /// Treats `self` as an annual fee and prorates it over `duration_ms`.
+    pub fn of_prorated(&self, amount: u128, duration_ms: u64) -> Option<u128> {
+        prorate_annual(self.of(amount)?, duration_ms)
+    }
+}
+
+/// Prorates an annual amount over `duration_ms`, rounding up.
+pub fn prorate_annual(annual_amount: u128, duration_ms: u64) -> Option<u128> {
+    let year = u128::from(MS_PER_YEAR);
+    let duration = u128::from(duration_ms);
+
+    // Split to avoid overflowing `annual_amount * duration`.
+    (annual_amount / year)
+        .checked_mul(duration)?
+        .checked_add((annual_amount % year * duration).div_ceil(year))
 }
 
+/// A fee that is charged in full at time zero and decays to nothing once
+/// `duration` (in milliseconds) has elapsed, e.g. an early withdrawal fee.
 #[derive(Clone, Debug)]
 #[near(serializers = [json, borsh])]
 pub struct TimeBasedFee {
@@ -40,25 +70,113 @@ pub enum TimeBasedFeeFunction {
 }
 
 impl TimeBasedFee {
+    /// `time` is the number of milliseconds elapsed since the start of the
+    /// fee period (e.g. since the deposit was made).
     pub fn of(&self, amount: u128, time: u64) -> Option<u128> {
         let base_fee = self.fee.of(amount)?;
 
-        if self.duration.0 == 0 {
+        if time >= self.duration.0 {
             return Some(0);
         }
 
+        let remaining = self.duration.0 - time;
+
         match self.behavior {
             TimeBasedFeeFunction::Fixed => Some(base_fee),
             TimeBasedFeeFunction::Linear => Some(
                 base_fee
-                    .checked_mul(u128::from(time))?
+                    .checked_mul(u128::from(remaining))?
                     .div_ceil(u128::from(self.duration.0)),
             ),
             TimeBasedFeeFunction::Logarithmic => Some(
-                ((base_fee as f64 * f64::log2((1 + time - self.duration.0) as f64))
-                    / f64::log2((1 + time) as f64))
+                ((base_fee as f64 * f64::log2((1 + remaining) as f64))
+                    / f64::log2((1 + self.duration.0) as f64))
                 .ceil() as u128,
             ),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fee_is_valid() {
+        assert!(Fee::Flat(u128::MAX.into()).is_valid());
+        assert!(Fee::Proportional(Rational::new(0, 1)).is_valid());
+        assert!(Fee::Proportional(Rational::new(1, 1)).is_valid());
+        assert!(!Fee::Proportional(Rational::new(101, 100)).is_valid());
+        assert!(!Fee::Proportional(Rational::new(0, 0)).is_valid());
+    }
+
+    fn time_based_fee(behavior: TimeBasedFeeFunction) -> TimeBasedFee {
+        TimeBasedFee {
+            fee: Fee::Proportional(Rational::new(1, 100)),
+            duration: 1000.into(),
+            behavior,
+        }
+    }
+
+    #[test]
+    fn time_based_fee_expires() {
+        for behavior in [
+            TimeBasedFeeFunction::Fixed,
+            TimeBasedFeeFunction::Linear,
+            TimeBasedFeeFunction::Logarithmic,
+        ] {
+            let fee = time_based_fee(behavior);
+            assert_eq!(fee.of(10_000, 0), Some(100));
+            assert_eq!(fee.of(10_000, 1000), Some(0));
+            assert_eq!(fee.of(10_000, u64::MAX), Some(0));
+        }
+    }
+
+    #[test]
+    fn time_based_fee_decays() {
+        assert_eq!(
+            time_based_fee(TimeBasedFeeFunction::Fixed).of(10_000, 999),
+            Some(100)
+        );
+        assert_eq!(
+            time_based_fee(TimeBasedFeeFunction::Linear).of(10_000, 250),
+            Some(75)
+        );
+        assert_eq!(
+            time_based_fee(TimeBasedFeeFunction::Linear).of(10_000, 999),
+            Some(1)
+        );
+
+        let logarithmic = time_based_fee(TimeBasedFeeFunction::Logarithmic);
+        let early = logarithmic.of(10_000, 250).unwrap();
+        let late = logarithmic.of(10_000, 750).unwrap();
+        assert!(early < 100 && late < early && late > 0);
+    }
+
+    #[test]
+    fn prorated_fee() {
+        let fee = Fee::Proportional(Rational::new(5, 100));
+        assert_eq!(fee.of_prorated(1_000_000, MS_PER_YEAR), Some(50_000));
+        assert_eq!(fee.of_prorated(1_000_000, MS_PER_YEAR / 2), Some(25_000));
+        assert_eq!(fee.of_prorated(1_000_000, 0), Some(0));
+        // Rounds up.
+        assert_eq!(fee.of_prorated(1_000_000, 1), Some(1));
+        assert_eq!(
+            fee.of_prorated(u128::MAX / 100, MS_PER_YEAR),
+            Some((u128::MAX / 100).div_ceil(20)),
+        );
+
+        let fee = Fee::Flat(1200.into());
+        assert_eq!(fee.of_prorated(1, MS_PER_YEAR / 12), Some(100));
+    }
+
+    #[test]
+    fn time_based_fee_zero_duration() {
+        let fee = TimeBasedFee {
+            fee: Fee::Flat(5.into()),
+            duration: 0.into(),
+            behavior: TimeBasedFeeFunction::Fixed,
+        };
+        assert_eq!(fee.of(10_000, 0), Some(0));
+    }
+}
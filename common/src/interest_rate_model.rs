@@ -0,0 +1,160 @@
+use near_sdk::near;
+
+use crate::rational::Rational;
+
+/// Rates and utilization are evaluated in parts per billion, so that
+/// intermediate values stay small enough not to overflow.
+const PRECISION: u128 = 1_000_000_000;
+
+/// Annual borrow interest rate as a function of borrow asset utilization
+/// (`used / deposited`, see [`crate::market::BorrowAssetMetrics`]).
+#[derive(Clone, Debug)]
+#[near(serializers = [json, borsh])]
+pub enum InterestRateModel {
+    /// The rate does not depend on utilization.
+    Fixed { rate: Rational<u16> },
+    /// Kinked linear curve. The rate rises from `base_rate` by `slope1` as
+    /// utilization approaches `optimal_utilization`, and then by a further
+    /// `slope2` as utilization approaches 100%.
+    JumpRate {
+        base_rate: Rational<u16>,
+        slope1: Rational<u16>,
+        optimal_utilization: Rational<u16>,
+        slope2: Rational<u16>,
+    },
+}
+
+fn to_precision(rational: Rational<u16>) -> Option<u128> {
+    u128::from(rational.numerator())
+        .checked_mul(PRECISION)?
+        .checked_div(u128::from(rational.denominator()))
+}
+
+fn utilization(used: u128, deposited: u128) -> u128 {
+    if deposited == 0 {
+        return 0;
+    }
+
+    let utilization = used
+        .checked_mul(PRECISION)
+        .map(|scaled_used| scaled_used / deposited)
+        // If `used * PRECISION` overflows, `deposited >= used` is large
+        // enough for this to be accurate.
+        .unwrap_or_else(|| used / (deposited / PRECISION));
+
+    utilization.min(PRECISION)
+}
+
+impl InterestRateModel {
+    fn borrow_rate_scaled(&self, utilization: u128) -> Option<u128> {
+        match self {
+            Self::Fixed { rate } => to_precision(*rate),
+            Self::JumpRate {
+                base_rate,
+                slope1,
+                optimal_utilization,
+                slope2,
+            } => {
+                let base_rate = to_precision(*base_rate)?;
+                let slope1 = to_precision(*slope1)?;
+                let optimal_utilization = to_precision(*optimal_utilization)?.min(PRECISION);
+                let slope2 = to_precision(*slope2)?;
+
+                if utilization <= optimal_utilization {
+                    let variable_rate = slope1
+                        .checked_mul(utilization)?
+                        .checked_div(optimal_utilization)
+                        // Zero utilization at zero optimal utilization.
+                        .unwrap_or(0);
+                    base_rate.checked_add(variable_rate)
+                } else {
+                    // `utilization <= PRECISION`, so the denominator is
+                    // non-zero.
+                    let excess_rate = slope2.checked_mul(utilization - optimal_utilization)?
+                        / (PRECISION - optimal_utilization);
+                    base_rate.checked_add(slope1)?.checked_add(excess_rate)
+                }
+            }
+        }
+    }
+
+    /// Annual borrow rate, given the amount of the borrow asset that is
+    /// deposited and the amount that is lent out.
+    pub fn borrow_rate(&self, used: u128, deposited: u128) -> Option<Rational<u128>> {
+        let rate = self.borrow_rate_scaled(utilization(used, deposited))?;
+        Some(Rational::new(rate, PRECISION))
+    }
+
+    /// Annual yield for supply providers, assuming all interest is
+    /// distributed to them.
+    pub fn supply_rate(&self, used: u128, deposited: u128) -> Option<Rational<u128>> {
+        let utilization = utilization(used, deposited);
+        let rate = self
+            .borrow_rate_scaled(utilization)?
+            .checked_mul(utilization)?
+            / PRECISION;
+        Some(Rational::new(rate, PRECISION))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jump_rate() -> InterestRateModel {
+        InterestRateModel::JumpRate {
+            base_rate: Rational::new(2, 100),
+            slope1: Rational::new(4, 100),
+            optimal_utilization: Rational::new(80, 100),
+            slope2: Rational::new(75, 100),
+        }
+    }
+
+    #[test]
+    fn jump_rate_curve() {
+        let model = jump_rate();
+
+        assert_eq!(model.borrow_rate(0, 0), Some(Rational::new(2, 100)));
+        assert_eq!(model.borrow_rate(0, 1000), Some(Rational::new(2, 100)));
+        assert_eq!(model.borrow_rate(400, 1000), Some(Rational::new(4, 100)));
+        assert_eq!(model.borrow_rate(800, 1000), Some(Rational::new(6, 100)));
+        assert_eq!(model.borrow_rate(900, 1000), Some(Rational::new(435, 1000)));
+        assert_eq!(model.borrow_rate(1000, 1000), Some(Rational::new(81, 100)));
+    }
+
+    #[test]
+    fn jump_rate_edge_cases() {
+        let model = InterestRateModel::JumpRate {
+            base_rate: Rational::new(0, 1),
+            slope1: Rational::new(1, 10),
+            optimal_utilization: Rational::new(0, 1),
+            slope2: Rational::new(1, 1),
+        };
+        assert_eq!(model.borrow_rate(0, 1000), Some(Rational::new(0, 1)));
+        assert_eq!(model.borrow_rate(500, 1000), Some(Rational::new(6, 10)));
+
+        let model = InterestRateModel::JumpRate {
+            base_rate: Rational::new(0, 1),
+            slope1: Rational::new(1, 10),
+            optimal_utilization: Rational::new(1, 1),
+            slope2: Rational::new(1, 1),
+        };
+        assert_eq!(model.borrow_rate(1000, 1000), Some(Rational::new(1, 10)));
+
+        // Does not overflow with large balances.
+        assert_eq!(
+            jump_rate().borrow_rate(u128::MAX / 2, u128::MAX),
+            Some(Rational::new(45, 1000)),
+        );
+    }
+
+    #[test]
+    fn supply_rate() {
+        let model = jump_rate();
+        assert_eq!(model.supply_rate(0, 1000), Some(Rational::new(0, 1)));
+        assert_eq!(
+            model.supply_rate(500, 1000),
+            Some(Rational::new(225, 10000))
+        );
+    }
+}
@@ -1,7 +1,10 @@
 pub mod asset;
 pub mod borrow;
 pub mod fee;
+pub mod interest_rate_model;
 pub mod market;
+pub mod number;
+pub mod oracle;
 pub mod rational;
 pub mod supply;
 pub mod withdrawal_queue;
@@ -1,3 +1,5 @@
+use std::fmt::Display;
+
 use near_sdk::{
     json_types::{U128, U64},
     near, AccountId,
@@ -5,12 +7,15 @@ use near_sdk::{
 
 use crate::{
     asset::FungibleAsset,
-    borrow::BorrowPosition,
+    borrow::{BorrowPosition, BorrowPositionDetails, BorrowStatus},
     fee::{Fee, TimeBasedFee},
+    interest_rate_model::InterestRateModel,
+    number::U512,
+    oracle::PriceOracleConfiguration,
     rational::Rational,
 };
 
-use super::{LiquidationSpread, OraclePriceProof};
+use super::{LiquidationPolicy, LiquidationSpread, OraclePriceProof};
 
 #[derive(Clone, Debug)]
 #[near(serializers = [json, borsh])]
@@ -18,7 +23,10 @@ pub struct MarketConfiguration {
     pub borrow_asset: FungibleAsset,
     pub collateral_asset: FungibleAsset,
     pub balance_oracle_account_id: AccountId,
-    pub liquidator_account_id: AccountId,
+    pub price_oracle_configuration: PriceOracleConfiguration,
+    pub liquidation_policy: LiquidationPolicy,
+    /// Receives the protocol's share of liquidation proceeds.
+    pub protocol_account_id: AccountId,
     pub minimum_collateral_ratio_per_borrow: Rational<u16>,
     /// How much of the deposited principal may be lent out (up to 100%)?
     /// This is a matter of protection for supply providers.
@@ -29,15 +37,196 @@ pub struct MarketConfiguration {
     /// the borrow asset and is paid by the borrowing account during repayment
     /// (or liquidation).
     pub origination_fee: Fee,
+    /// Charged on the borrow asset liability in addition to the interest
+    /// from `interest_rate_model`. It accrues continuously, prorated by block
+    /// timestamp, and is distributed to supply providers. A flat fee is
+    /// charged per borrow position.
     pub annual_maintenance_fee: Fee,
+    pub interest_rate_model: InterestRateModel,
+    /// Borrow positions open for longer than this (in milliseconds) can be
+    /// liquidated regardless of their collateral ratio.
     pub maximum_borrow_duration: Option<U64>,
+    /// Bounds on the amount of a single borrow.
     pub minimum_borrow_amount: U128,
     pub maximum_borrow_amount: U128,
     pub withdrawal_fee: TimeBasedFee,
     pub liquidation_spread: LiquidationSpread,
+    /// Maximum share of a borrow position's liability that can be repaid in
+    /// a single liquidation.
+    pub liquidation_close_factor: Rational<u16>,
+    /// Liquidated borrowers lose collateral worth the repaid amount plus
+    /// this share of it. The liquidator pays for the extra collateral in the
+    /// borrow asset, and that margin is split according to
+    /// `liquidation_spread`.
+    pub liquidation_bonus: Rational<u16>,
+}
+
+/// Reasons for [`MarketConfiguration::validate`] to reject a configuration.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[near(serializers = [json])]
+pub enum MarketConfigurationError {
+    SameBorrowAndCollateralAsset,
+    /// Names the field with a zero denominator.
+    ZeroDenominator(String),
+    MinimumCollateralRatioBelowOne,
+    MaximumBorrowAssetUsageRatioAboveOne,
+    /// Names the proportional fee that exceeds 100%.
+    FeeAboveOne(String),
+    MinimumBorrowAmountAboveMaximum,
+    ZeroLiquidationSpread,
+    LiquidationCloseFactorOutOfRange,
+}
+
+impl Display for MarketConfigurationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::SameBorrowAndCollateralAsset => {
+                f.write_str("Borrow asset and collateral asset must be different")
+            }
+            Self::ZeroDenominator(field) => write!(f, "{field} has a zero denominator"),
+            Self::MinimumCollateralRatioBelowOne => {
+                f.write_str("Minimum collateral ratio must be at least 100%")
+            }
+            Self::MaximumBorrowAssetUsageRatioAboveOne => {
+                f.write_str("Maximum borrow asset usage ratio must be at most 100%")
+            }
+            Self::FeeAboveOne(field) => write!(f, "{field} must be at most 100%"),
+            Self::MinimumBorrowAmountAboveMaximum => {
+                f.write_str("Minimum borrow amount exceeds maximum borrow amount")
+            }
+            Self::ZeroLiquidationSpread => {
+                f.write_str("Liquidation spread must have at least one nonzero weight")
+            }
+            Self::LiquidationCloseFactorOutOfRange => {
+                f.write_str("Liquidation close factor must be greater than 0% and at most 100%")
+            }
+        }
+    }
+}
+
+fn require_nonzero_denominator(
+    field: &str,
+    ratio: Rational<u16>,
+) -> Result<(), MarketConfigurationError> {
+    if ratio.denominator() == 0 {
+        return Err(MarketConfigurationError::ZeroDenominator(field.to_string()));
+    }
+
+    Ok(())
+}
+
+fn validate_fee(field: &str, fee: &Fee) -> Result<(), MarketConfigurationError> {
+    if let Fee::Proportional(ratio) = fee {
+        require_nonzero_denominator(field, *ratio)?;
+    }
+
+    if !fee.is_valid() {
+        return Err(MarketConfigurationError::FeeAboveOne(field.to_string()));
+    }
+
+    Ok(())
+}
+
+/// `numerator / denominator` as a `Rational<u128>`, dropping low bits of both
+/// if they do not fit. `None` if `denominator` is zero.
+fn approximate_ratio(numerator: U512, denominator: U512) -> Option<Rational<u128>> {
+    if denominator.is_zero() {
+        return None;
+    }
+
+    let shift = numerator.bits().max(denominator.bits()).saturating_sub(128);
+    let numerator = (numerator >> shift).as_u128();
+    let denominator = (denominator >> shift).as_u128();
+
+    if denominator == 0 {
+        // The ratio is at least 2^127.
+        return Some(Rational::new(u128::MAX, 1));
+    }
+
+    Some(Rational::new(numerator, denominator))
+}
+
+/// Result of [`MarketConfiguration::liquidation_amounts`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LiquidationAmounts {
+    /// Borrow asset liability repaid by the liquidator.
+    pub repay: u128,
+    /// Collateral asset received by the liquidator.
+    pub collateral: u128,
+    /// Borrow asset paid by the liquidator on top of `repay` for the
+    /// liquidation bonus.
+    pub margin: u128,
 }
 
 impl MarketConfiguration {
+    /// Checks the configuration for values that would make the market
+    /// unusable or unsafe. Returns the first problem found.
+    pub fn validate(&self) -> Result<(), MarketConfigurationError> {
+        if self.borrow_asset == self.collateral_asset {
+            return Err(MarketConfigurationError::SameBorrowAndCollateralAsset);
+        }
+
+        require_nonzero_denominator(
+            "price_oracle_configuration.maximum_confidence_ratio",
+            self.price_oracle_configuration.maximum_confidence_ratio,
+        )?;
+
+        let mcr = self.minimum_collateral_ratio_per_borrow;
+        require_nonzero_denominator("minimum_collateral_ratio_per_borrow", mcr)?;
+        if mcr.numerator() < mcr.denominator() {
+            return Err(MarketConfigurationError::MinimumCollateralRatioBelowOne);
+        }
+
+        let usage_ratio = self.maximum_borrow_asset_usage_ratio;
+        require_nonzero_denominator("maximum_borrow_asset_usage_ratio", usage_ratio)?;
+        if usage_ratio.numerator() > usage_ratio.denominator() {
+            return Err(MarketConfigurationError::MaximumBorrowAssetUsageRatioAboveOne);
+        }
+
+        validate_fee("origination_fee", &self.origination_fee)?;
+        validate_fee("annual_maintenance_fee", &self.annual_maintenance_fee)?;
+        validate_fee("withdrawal_fee", &self.withdrawal_fee.fee)?;
+
+        match self.interest_rate_model {
+            InterestRateModel::Fixed { rate } => {
+                require_nonzero_denominator("interest_rate_model.rate", rate)?;
+            }
+            InterestRateModel::JumpRate {
+                base_rate,
+                slope1,
+                optimal_utilization,
+                slope2,
+            } => {
+                require_nonzero_denominator("interest_rate_model.base_rate", base_rate)?;
+                require_nonzero_denominator("interest_rate_model.slope1", slope1)?;
+                require_nonzero_denominator(
+                    "interest_rate_model.optimal_utilization",
+                    optimal_utilization,
+                )?;
+                require_nonzero_denominator("interest_rate_model.slope2", slope2)?;
+            }
+        }
+
+        if self.minimum_borrow_amount > self.maximum_borrow_amount {
+            return Err(MarketConfigurationError::MinimumBorrowAmountAboveMaximum);
+        }
+
+        let spread = &self.liquidation_spread;
+        if spread.supply_position.0 == 0 && spread.liquidator.0 == 0 && spread.protocol.0 == 0 {
+            return Err(MarketConfigurationError::ZeroLiquidationSpread);
+        }
+
+        let close_factor = self.liquidation_close_factor;
+        require_nonzero_denominator("liquidation_close_factor", close_factor)?;
+        if close_factor.numerator() == 0 || close_factor.numerator() > close_factor.denominator() {
+            return Err(MarketConfigurationError::LiquidationCloseFactorOutOfRange);
+        }
+
+        require_nonzero_denominator("liquidation_bonus", self.liquidation_bonus)?;
+
+        Ok(())
+    }
+
     pub fn is_healthy(
         &self,
         borrow_position: &BorrowPosition,
@@ -46,59 +235,780 @@ impl MarketConfiguration {
             borrow_asset_price,
         }: OraclePriceProof,
     ) -> bool {
-        let scaled_collateral_value = borrow_position.collateral_asset_deposit.0
-            * collateral_asset_price.numerator()
-            * borrow_asset_price.denominator()
-            * self.minimum_collateral_ratio_per_borrow.denominator() as u128;
-        let scaled_borrow_value = borrow_position.borrow_asset_liability.0
-            * borrow_asset_price.numerator()
-            * collateral_asset_price.denominator()
-            * self.minimum_collateral_ratio_per_borrow.numerator() as u128;
+        // Each side is a product of four values below 2^128, so it cannot
+        // overflow 512 bits.
+        let scaled_collateral_value = U512::from(borrow_position.collateral_asset_deposit.0)
+            * U512::from(collateral_asset_price.numerator())
+            * U512::from(borrow_asset_price.denominator())
+            * U512::from(self.minimum_collateral_ratio_per_borrow.denominator());
+        let scaled_borrow_value = U512::from(borrow_position.borrow_asset_liability.0)
+            * U512::from(borrow_asset_price.numerator())
+            * U512::from(collateral_asset_price.denominator())
+            * U512::from(self.minimum_collateral_ratio_per_borrow.numerator());
 
         scaled_collateral_value >= scaled_borrow_value
     }
+
+    /// `borrow_asset_available` is the amount of the borrow asset that the
+    /// market can currently lend out.
+    pub fn borrow_position_details(
+        &self,
+        borrow_position: &BorrowPosition,
+        oracle_price_proof: OraclePriceProof,
+        borrow_asset_available: u128,
+        block_timestamp_ms: u64,
+    ) -> BorrowPositionDetails {
+        let OraclePriceProof {
+            collateral_asset_price,
+            borrow_asset_price,
+        } = oracle_price_proof;
+        let mcr_numerator = U512::from(self.minimum_collateral_ratio_per_borrow.numerator());
+        let mcr_denominator = U512::from(self.minimum_collateral_ratio_per_borrow.denominator());
+        let collateral = borrow_position.collateral_asset_deposit.0;
+        let liability = borrow_position.borrow_asset_liability.0;
+
+        // Values of the collateral and the liability, both multiplied by the
+        // denominators of the two prices.
+        let collateral_value = U512::from(collateral)
+            * U512::from(collateral_asset_price.numerator())
+            * U512::from(borrow_asset_price.denominator());
+        let borrow_value = U512::from(liability)
+            * U512::from(borrow_asset_price.numerator())
+            * U512::from(collateral_asset_price.denominator());
+
+        let (collateral_ratio, health_factor) = if liability == 0 {
+            (None, None)
+        } else {
+            (
+                approximate_ratio(collateral_value, borrow_value),
+                approximate_ratio(
+                    collateral_value * mcr_denominator,
+                    borrow_value * mcr_numerator,
+                ),
+            )
+        };
+
+        let liquidation_price = if liability == 0 || collateral == 0 {
+            None
+        } else {
+            approximate_ratio(
+                U512::from(liability) * U512::from(borrow_asset_price.numerator()) * mcr_numerator,
+                U512::from(collateral)
+                    * U512::from(borrow_asset_price.denominator())
+                    * mcr_denominator,
+            )
+        };
+
+        // Largest liability for which the position is healthy, with the same
+        // rounding as `is_healthy`.
+        let maximum_liability_divisor = U512::from(borrow_asset_price.numerator())
+            * U512::from(collateral_asset_price.denominator())
+            * mcr_numerator;
+        let maximum_liability = if maximum_liability_divisor.is_zero() {
+            u128::MAX
+        } else {
+            u128::try_from(collateral_value * mcr_denominator / maximum_liability_divisor)
+                .unwrap_or(u128::MAX)
+        };
+
+        let maximum_borrow_amount = self
+            .maximum_borrow_amount_for_liability(maximum_liability.saturating_sub(liability))
+            .min(self.maximum_borrow_amount.0)
+            .min(borrow_asset_available);
+        let maximum_borrow_amount = if maximum_borrow_amount < self.minimum_borrow_amount.0 {
+            0
+        } else {
+            maximum_borrow_amount
+        };
+
+        // Smallest collateral deposit for which the position is healthy.
+        let minimum_collateral_divisor = U512::from(collateral_asset_price.numerator())
+            * U512::from(borrow_asset_price.denominator())
+            * mcr_denominator;
+        let minimum_collateral = if liability == 0 {
+            0
+        } else if minimum_collateral_divisor.is_zero() {
+            u128::MAX
+        } else {
+            let minimum_collateral = (borrow_value * mcr_numerator + minimum_collateral_divisor
+                - 1)
+                / minimum_collateral_divisor;
+            u128::try_from(minimum_collateral).unwrap_or(u128::MAX)
+        };
+
+        BorrowPositionDetails {
+            status: self.borrow_status(borrow_position, oracle_price_proof, block_timestamp_ms),
+            collateral_ratio,
+            health_factor,
+            liquidation_price,
+            maximum_borrow_amount: maximum_borrow_amount.into(),
+            maximum_collateral_withdrawal: collateral.saturating_sub(minimum_collateral).into(),
+        }
+    }
+
+    /// Largest amount that can be borrowed such that it and its origination
+    /// fee add up to at most `liability`.
+    fn maximum_borrow_amount_for_liability(&self, liability: u128) -> u128 {
+        let liable_amount = |amount: u128| {
+            self.origination_fee
+                .of(amount)
+                .and_then(|fee| amount.checked_add(fee))
+        };
+
+        let mut amount = match self.origination_fee {
+            Fee::Flat(fee) => liability.saturating_sub(fee.0),
+            Fee::Proportional(rate) => {
+                if rate.denominator() == 0 {
+                    return 0;
+                }
+                let denominator = U512::from(rate.denominator()) + U512::from(rate.numerator());
+                (U512::from(liability) * U512::from(rate.denominator()) / denominator).as_u128()
+            }
+        };
+
+        // The proportional fee is rounded up, so the estimate may be slightly
+        // too large.
+        while amount > 0 && liable_amount(amount).map_or(true, |liable| liable > liability) {
+            amount -= 1;
+        }
+
+        amount
+    }
+
+    /// Unhealthy positions are reported as [`BorrowStatus::Liquidation`]
+    /// even if they have also expired.
+    pub fn borrow_status(
+        &self,
+        borrow_position: &BorrowPosition,
+        oracle_price_proof: OraclePriceProof,
+        block_timestamp_ms: u64,
+    ) -> BorrowStatus {
+        if !self.is_healthy(borrow_position, oracle_price_proof) {
+            BorrowStatus::Liquidation
+        } else if self.is_expired(borrow_position, block_timestamp_ms) {
+            BorrowStatus::Expired
+        } else {
+            BorrowStatus::Healthy
+        }
+    }
+
+    pub fn is_expired(&self, borrow_position: &BorrowPosition, block_timestamp_ms: u64) -> bool {
+        self.maximum_borrow_duration
+            .is_some_and(|maximum_borrow_duration| {
+                borrow_position.started_at_block_timestamp_ms.is_some()
+                    && borrow_position.borrow_duration_ms(block_timestamp_ms)
+                        >= maximum_borrow_duration.0
+            })
+    }
+
+    /// Maximum liability that can be repaid in a single liquidation, rounded
+    /// up so that dust positions can be closed.
+    pub fn maximum_liquidation_repay(&self, liability: u128) -> u128 {
+        let close_factor = self.liquidation_close_factor;
+        let repay = U512::from(liability) * U512::from(close_factor.numerator());
+        let repay = (repay + U512::from(close_factor.denominator()) - 1)
+            / U512::from(close_factor.denominator());
+        u128::try_from(repay).unwrap_or(u128::MAX).min(liability)
+    }
+
+    /// Price of the collateral asset in units of the borrow asset, including
+    /// the liquidation bonus, as `(numerator, denominator)`.
+    fn liquidation_price(
+        &self,
+        OraclePriceProof {
+            collateral_asset_price,
+            borrow_asset_price,
+        }: OraclePriceProof,
+    ) -> (U512, U512) {
+        let bonus = self.liquidation_bonus;
+        let numerator = U512::from(borrow_asset_price.numerator())
+            * U512::from(collateral_asset_price.denominator())
+            * (U512::from(bonus.denominator()) + U512::from(bonus.numerator()));
+        let denominator = U512::from(borrow_asset_price.denominator())
+            * U512::from(collateral_asset_price.numerator())
+            * U512::from(bonus.denominator());
+        (numerator, denominator)
+    }
+
+    /// Collateral received for repaying `repay` of the borrow asset, rounded
+    /// down.
+    pub fn liquidation_collateral_amount(
+        &self,
+        repay: u128,
+        oracle_price_proof: OraclePriceProof,
+    ) -> Option<u128> {
+        let (numerator, denominator) = self.liquidation_price(oracle_price_proof);
+        if denominator.is_zero() {
+            return None;
+        }
+        u128::try_from(U512::from(repay) * numerator / denominator).ok()
+    }
+
+    /// Borrow asset that must be repaid to receive `collateral`, rounded up.
+    pub fn liquidation_repay_amount(
+        &self,
+        collateral: u128,
+        oracle_price_proof: OraclePriceProof,
+    ) -> Option<u128> {
+        let (numerator, denominator) = self.liquidation_price(oracle_price_proof);
+        if numerator.is_zero() {
+            return None;
+        }
+        let repay = (U512::from(collateral) * denominator + numerator - 1) / numerator;
+        u128::try_from(repay).ok()
+    }
+
+    /// Splits `amount` of the borrow asset sent by a liquidator into the
+    /// liability it repays, the margin it pays for the bonus and the
+    /// collateral it buys. Any remainder is to be refunded. If the collateral
+    /// deposit does not cover the repayment plus the bonus, all of it is sold
+    /// for the amount it is worth.
+    pub fn liquidation_amounts(
+        &self,
+        borrow_position: &BorrowPosition,
+        amount: u128,
+        oracle_price_proof: OraclePriceProof,
+    ) -> Option<LiquidationAmounts> {
+        let bonus = self.liquidation_bonus;
+        // Largest repayment whose margin can also be paid from `amount`.
+        let affordable = u128::try_from(
+            U512::from(amount) * U512::from(bonus.denominator())
+                / (U512::from(bonus.denominator()) + U512::from(bonus.numerator())),
+        )
+        .ok()?;
+        let repay = affordable
+            .min(self.maximum_liquidation_repay(borrow_position.borrow_asset_liability.0));
+        let collateral = self.liquidation_collateral_amount(repay, oracle_price_proof)?;
+        let deposit = borrow_position.collateral_asset_deposit.0;
+
+        let (repay, collateral) = if collateral <= deposit {
+            (repay, collateral)
+        } else {
+            (
+                self.liquidation_repay_amount(deposit, oracle_price_proof)?
+                    .min(repay),
+                deposit,
+            )
+        };
+
+        // The bonus on `repay`, rounded down. `repay + margin <= amount`.
+        let margin =
+            U512::from(repay) * U512::from(bonus.numerator()) / U512::from(bonus.denominator());
+
+        Some(LiquidationAmounts {
+            repay,
+            collateral,
+            margin: u128::try_from(margin).ok()?,
+        })
+    }
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use crate::{
         asset::FungibleAsset,
+        borrow::{BorrowPosition, BorrowPositionDetails, BorrowStatus},
         fee::{Fee, TimeBasedFee, TimeBasedFeeFunction},
-        market::{LiquidationSpread, MarketConfiguration},
+        interest_rate_model::InterestRateModel,
+        market::{
+            LiquidationAmounts, LiquidationPolicy, LiquidationSpread, MarketConfiguration,
+            MarketConfigurationError, OraclePriceProof,
+        },
+        oracle::{PriceIdentifier, PriceOracleConfiguration},
         rational::Rational,
     };
+    use proptest::prelude::*;
 
-    // {"configuration":{"borrow_asset":{"Nep141":"usdt.fakes.testnet"},"collateral_asset":{"Nep141":"wrap.testnet"},"balance_oracle_account_id":"root.testnet","liquidator_account_id":"templar-in-training.testnet","minimum_collateral_ratio_per_borrow":[6,5],"maximum_borrow_asset_usage_ratio":[99,100],"origination_fee":{"Proportional":[1,100]},"annual_maintenance_fee":{"Flat":"0"},"maximum_borrow_duration":null,"minimum_borrow_amount":"1","maximum_borrow_amount":"340282366920938463463374607431768211455","withdrawal_fee":{"fee":{"Flat":"0"},"duration":"0","behavior":"Fixed"},"liquidation_spread":{"supply_position":"6","liquidator":"1","protocol":"1"}}}
+    // {"configuration":{"borrow_asset":{"Nep141":"usdt.fakes.testnet"},"collateral_asset":{"Nep141":"wrap.testnet"},"balance_oracle_account_id":"root.testnet","price_oracle_configuration":{"account_id":"pyth-oracle.testnet","collateral_asset_price_id":"27e867f0f4f61076456d1a73b14c7edc1cf5cef4f4d6193a33424288f11bd0f4","collateral_asset_decimals":24,"borrow_asset_price_id":"1fc18861232290221461220bd4e2acd1dcdfbc89c84092c93c18bdc7756c1588","borrow_asset_decimals":6,"price_maximum_age_s":60,"maximum_confidence_ratio":[1,100]},"liquidation_policy":{"Single":"templar-in-training.testnet"},"protocol_account_id":"templar-in-training.testnet","minimum_collateral_ratio_per_borrow":[6,5],"maximum_borrow_asset_usage_ratio":[99,100],"origination_fee":{"Proportional":[1,100]},"annual_maintenance_fee":{"Flat":"0"},"interest_rate_model":{"JumpRate":{"base_rate":[0,1],"slope1":[1,25],"optimal_utilization":[4,5],"slope2":[3,4]}},"maximum_borrow_duration":null,"minimum_borrow_amount":"1","maximum_borrow_amount":"340282366920938463463374607431768211455","withdrawal_fee":{"fee":{"Flat":"0"},"duration":"0","behavior":"Fixed"},"liquidation_spread":{"supply_position":"6","liquidator":"1","protocol":"1"},"liquidation_close_factor":[1,2],"liquidation_bonus":[1,20]}}
+
+    /// Shared by the unit tests of the market.
+    pub(crate) fn sample_configuration() -> MarketConfiguration {
+        MarketConfiguration {
+            borrow_asset: FungibleAsset::Nep141("usdt.fakes.testnet".parse().unwrap()),
+            collateral_asset: FungibleAsset::Nep141("wrap.testnet".parse().unwrap()),
+            balance_oracle_account_id: "root.testnet".parse().unwrap(),
+            price_oracle_configuration: PriceOracleConfiguration {
+                account_id: "pyth-oracle.testnet".parse().unwrap(),
+                collateral_asset_price_id: PriceIdentifier(
+                    "27e867f0f4f61076456d1a73b14c7edc1cf5cef4f4d6193a33424288f11bd0f4".to_string(),
+                ),
+                collateral_asset_decimals: 24,
+                borrow_asset_price_id: PriceIdentifier(
+                    "1fc18861232290221461220bd4e2acd1dcdfbc89c84092c93c18bdc7756c1588".to_string(),
+                ),
+                borrow_asset_decimals: 6,
+                price_maximum_age_s: 60,
+                maximum_confidence_ratio: Rational::new(1, 100),
+            },
+            liquidation_policy: LiquidationPolicy::Single(
+                "templar-in-training.testnet".parse().unwrap(),
+            ),
+            protocol_account_id: "templar-in-training.testnet".parse().unwrap(),
+            minimum_collateral_ratio_per_borrow: Rational::new(120, 100),
+            maximum_borrow_asset_usage_ratio: Rational::new(99, 100),
+            origination_fee: Fee::Proportional(Rational::new(1, 100)),
+            annual_maintenance_fee: Fee::Flat(0.into()),
+            interest_rate_model: InterestRateModel::JumpRate {
+                base_rate: Rational::new(0, 1),
+                slope1: Rational::new(4, 100),
+                optimal_utilization: Rational::new(80, 100),
+                slope2: Rational::new(75, 100),
+            },
+            maximum_borrow_duration: None,
+            minimum_borrow_amount: 1.into(),
+            maximum_borrow_amount: u128::MAX.into(),
+            withdrawal_fee: TimeBasedFee {
+                fee: Fee::Flat(0.into()),
+                duration: 0.into(),
+                behavior: TimeBasedFeeFunction::Fixed,
+            },
+            liquidation_spread: LiquidationSpread {
+                supply_position: 6.into(),
+                liquidator: 1.into(),
+                protocol: 1.into(),
+            },
+            liquidation_close_factor: Rational::new(50, 100),
+            liquidation_bonus: Rational::new(5, 100),
+        }
+    }
 
     // #[ignore = "generate sample configuration"]
     #[test]
     pub fn generate_sample_configuration() {
         println!(
             "{{\"configuration\":{}}}",
-            near_sdk::serde_json::to_string(&MarketConfiguration {
-                borrow_asset: FungibleAsset::Nep141("usdt.fakes.testnet".parse().unwrap()),
-                collateral_asset: FungibleAsset::Nep141("wrap.testnet".parse().unwrap()),
-                balance_oracle_account_id: "root.testnet".parse().unwrap(),
-                liquidator_account_id: "templar-in-training.testnet".parse().unwrap(),
-                minimum_collateral_ratio_per_borrow: Rational::new(120, 100),
-                maximum_borrow_asset_usage_ratio: Rational::new(99, 100),
-                origination_fee: Fee::Proportional(Rational::new(1, 100)),
-                annual_maintenance_fee: Fee::Flat(0.into()),
-                maximum_borrow_duration: None,
-                minimum_borrow_amount: 1.into(),
-                maximum_borrow_amount: u128::MAX.into(),
-                withdrawal_fee: TimeBasedFee {
-                    fee: Fee::Flat(0.into()),
-                    duration: 0.into(),
-                    behavior: TimeBasedFeeFunction::Fixed,
-                },
-                liquidation_spread: LiquidationSpread {
-                    supply_position: 6.into(),
-                    liquidator: 1.into(),
-                    protocol: 1.into(),
+            near_sdk::serde_json::to_string(&sample_configuration()).unwrap()
+        );
+    }
+
+    fn borrow_position(collateral: u128, liability: u128) -> BorrowPosition {
+        BorrowPosition {
+            collateral_asset_deposit: collateral.into(),
+            borrow_asset_liability: liability.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn maximum_liquidation_repay() {
+        let configuration = sample_configuration();
+        assert_eq!(configuration.maximum_liquidation_repay(1000), 500);
+        assert_eq!(configuration.maximum_liquidation_repay(1001), 501);
+        assert_eq!(configuration.maximum_liquidation_repay(1), 1);
+        assert_eq!(configuration.maximum_liquidation_repay(0), 0);
+        assert_eq!(
+            configuration.maximum_liquidation_repay(u128::MAX),
+            u128::MAX / 2 + 1,
+        );
+    }
+
+    #[test]
+    fn liquidation_amounts() {
+        let configuration = sample_configuration();
+        let price = OraclePriceProof {
+            collateral_asset_price: Rational::new(2, 1),
+            borrow_asset_price: Rational::new(1, 1),
+        };
+
+        // Limited by the close factor.
+        assert_eq!(
+            configuration.liquidation_amounts(&borrow_position(1000, 1000), 800, price),
+            Some(LiquidationAmounts {
+                repay: 500,
+                collateral: 262,
+                margin: 25,
+            }),
+        );
+
+        // Limited by the amount sent, which also pays the margin.
+        assert_eq!(
+            configuration.liquidation_amounts(&borrow_position(1000, 1000), 100, price),
+            Some(LiquidationAmounts {
+                repay: 95,
+                collateral: 49,
+                margin: 4,
+            }),
+        );
+
+        // Limited by the collateral deposit.
+        assert_eq!(
+            configuration.liquidation_amounts(&borrow_position(100, 1000), 500, price),
+            Some(LiquidationAmounts {
+                repay: 191,
+                collateral: 100,
+                margin: 9,
+            }),
+        );
+
+        // Invalid price.
+        assert_eq!(
+            configuration.liquidation_amounts(
+                &borrow_position(1000, 1000),
+                500,
+                OraclePriceProof {
+                    collateral_asset_price: Rational::new(0, 1),
+                    borrow_asset_price: Rational::new(1, 1),
                 },
-            })
-            .unwrap()
+            ),
+            None,
+        );
+    }
+
+    #[test]
+    fn validate() {
+        assert_eq!(sample_configuration().validate(), Ok(()));
+
+        let invalid = |f: fn(&mut MarketConfiguration)| {
+            let mut configuration = sample_configuration();
+            f(&mut configuration);
+            configuration.validate().unwrap_err()
+        };
+
+        assert_eq!(
+            invalid(|c| c.collateral_asset = c.borrow_asset.clone()),
+            MarketConfigurationError::SameBorrowAndCollateralAsset,
+        );
+        assert_eq!(
+            invalid(|c| c.minimum_collateral_ratio_per_borrow = Rational::new(1, 0)),
+            MarketConfigurationError::ZeroDenominator(
+                "minimum_collateral_ratio_per_borrow".to_string()
+            ),
+        );
+        assert_eq!(
+            invalid(|c| c.minimum_collateral_ratio_per_borrow = Rational::new(99, 100)),
+            MarketConfigurationError::MinimumCollateralRatioBelowOne,
+        );
+        assert_eq!(
+            invalid(|c| c.maximum_borrow_asset_usage_ratio = Rational::new(101, 100)),
+            MarketConfigurationError::MaximumBorrowAssetUsageRatioAboveOne,
+        );
+        assert_eq!(
+            invalid(|c| c.origination_fee = Fee::Proportional(Rational::new(0, 0))),
+            MarketConfigurationError::ZeroDenominator("origination_fee".to_string()),
+        );
+        assert_eq!(
+            invalid(|c| c.withdrawal_fee.fee = Fee::Proportional(Rational::new(2, 1))),
+            MarketConfigurationError::FeeAboveOne("withdrawal_fee".to_string()),
+        );
+        assert_eq!(
+            invalid(|c| c.interest_rate_model = InterestRateModel::Fixed {
+                rate: Rational::new(1, 0)
+            }),
+            MarketConfigurationError::ZeroDenominator("interest_rate_model.rate".to_string()),
+        );
+        assert_eq!(
+            invalid(|c| c.maximum_borrow_amount = 0.into()),
+            MarketConfigurationError::MinimumBorrowAmountAboveMaximum,
+        );
+        assert_eq!(
+            invalid(|c| c.liquidation_spread = LiquidationSpread {
+                supply_position: 0.into(),
+                liquidator: 0.into(),
+                protocol: 0.into(),
+            }),
+            MarketConfigurationError::ZeroLiquidationSpread,
+        );
+        assert_eq!(
+            invalid(|c| c.liquidation_close_factor = Rational::new(0, 1)),
+            MarketConfigurationError::LiquidationCloseFactorOutOfRange,
+        );
+        assert_eq!(
+            invalid(|c| c.liquidation_bonus = Rational::new(1, 0)),
+            MarketConfigurationError::ZeroDenominator("liquidation_bonus".to_string()),
+        );
+    }
+
+    #[test]
+    fn borrow_status_expiry() {
+        let mut configuration = sample_configuration();
+        let price = OraclePriceProof {
+            collateral_asset_price: Rational::new(1, 1),
+            borrow_asset_price: Rational::new(1, 1),
+        };
+        let mut position = borrow_position(1000, 100);
+        position.start_borrow(1000);
+
+        assert_eq!(
+            configuration.borrow_status(&position, price, 1_000_000),
+            BorrowStatus::Healthy,
+        );
+
+        configuration.maximum_borrow_duration = Some(500.into());
+        assert_eq!(
+            configuration.borrow_status(&position, price, 1499),
+            BorrowStatus::Healthy,
+        );
+        assert_eq!(
+            configuration.borrow_status(&position, price, 1500),
+            BorrowStatus::Expired,
+        );
+
+        position.collateral_asset_deposit = 100.into();
+        assert_eq!(
+            configuration.borrow_status(&position, price, 1500),
+            BorrowStatus::Liquidation,
+        );
+
+        // Fully repaid positions do not expire.
+        let mut position = borrow_position(1000, 100);
+        position.start_borrow(1000);
+        position.decrease_borrow_asset_liability(100);
+        assert_eq!(
+            configuration.borrow_status(&position, price, 1500),
+            BorrowStatus::Healthy,
         );
     }
+
+    fn is_healthy(
+        minimum_collateral_ratio: Rational<u16>,
+        collateral: u128,
+        liability: u128,
+        collateral_asset_price: Rational<u128>,
+        borrow_asset_price: Rational<u128>,
+    ) -> bool {
+        let mut configuration = sample_configuration();
+        configuration.minimum_collateral_ratio_per_borrow = minimum_collateral_ratio;
+        configuration.is_healthy(
+            &borrow_position(collateral, liability),
+            OraclePriceProof {
+                collateral_asset_price,
+                borrow_asset_price,
+            },
+        )
+    }
+
+    #[test]
+    fn is_healthy_with_24_decimal_tokens() {
+        // 1,000,000 tokens worth $3.50 each against 2,000,000 $1 tokens, with
+        // 24 decimals each.
+        let collateral_asset_price = Rational::new(35, 10u128.pow(25));
+        let borrow_asset_price = Rational::new(1, 10u128.pow(24));
+        let collateral = 1_000_000 * 10u128.pow(24);
+
+        assert!(is_healthy(
+            Rational::new(120, 100),
+            collateral,
+            2_000_000 * 10u128.pow(24),
+            collateral_asset_price,
+            borrow_asset_price,
+        ));
+        assert!(!is_healthy(
+            Rational::new(120, 100),
+            collateral,
+            3_000_000 * 10u128.pow(24),
+            collateral_asset_price,
+            borrow_asset_price,
+        ));
+    }
+
+    /// Full product of `a * b` as `(high, low)` halves, computed from 64-bit
+    /// limbs, independently of [`crate::number::U512`].
+    fn widening_mul(a: u128, b: u128) -> (u128, u128) {
+        const MASK: u128 = u64::MAX as u128;
+        let (a_high, a_low) = (a >> 64, a & MASK);
+        let (b_high, b_low) = (b >> 64, b & MASK);
+
+        let low = a_low * b_low;
+        let middle_1 = a_high * b_low;
+        let middle_2 = a_low * b_high;
+        let high = a_high * b_high;
+
+        let carry = ((low >> 64) + (middle_1 & MASK) + (middle_2 & MASK)) >> 64;
+        let low = low
+            .wrapping_add(middle_1 << 64)
+            .wrapping_add(middle_2 << 64);
+        let high = high + (middle_1 >> 64) + (middle_2 >> 64) + carry;
+
+        (high, low)
+    }
+
+    #[test]
+    fn test_widening_mul() {
+        assert_eq!(widening_mul(0, u128::MAX), (0, 0));
+        assert_eq!(widening_mul(u128::MAX, 1), (0, u128::MAX));
+        assert_eq!(widening_mul(1 << 64, 1 << 64), (1, 0));
+        assert_eq!(widening_mul(u128::MAX, u128::MAX), (u128::MAX - 1, 1));
+    }
+
+    #[test]
+    fn borrow_position_details() {
+        let configuration = sample_configuration();
+        let price = OraclePriceProof {
+            collateral_asset_price: Rational::new(1, 1),
+            borrow_asset_price: Rational::new(1, 1),
+        };
+
+        assert_eq!(
+            configuration.borrow_position_details(&borrow_position(1200, 500), price, 10_000, 0),
+            BorrowPositionDetails {
+                status: BorrowStatus::Healthy,
+                collateral_ratio: Some(Rational::new(12, 5)),
+                health_factor: Some(Rational::new(2, 1)),
+                liquidation_price: Some(Rational::new(1, 2)),
+                // 495 + 1% origination fee (rounded up) = 500
+                maximum_borrow_amount: 495.into(),
+                maximum_collateral_withdrawal: 600.into(),
+            },
+        );
+
+        // Limited by availability.
+        assert_eq!(
+            configuration
+                .borrow_position_details(&borrow_position(1200, 500), price, 100, 0)
+                .maximum_borrow_amount,
+            100.into(),
+        );
+
+        assert_eq!(
+            configuration.borrow_position_details(&borrow_position(1200, 0), price, 10_000, 0),
+            BorrowPositionDetails {
+                status: BorrowStatus::Healthy,
+                collateral_ratio: None,
+                health_factor: None,
+                liquidation_price: None,
+                maximum_borrow_amount: 990.into(),
+                maximum_collateral_withdrawal: 1200.into(),
+            },
+        );
+
+        let details =
+            configuration.borrow_position_details(&borrow_position(1200, 1100), price, 10_000, 0);
+        assert_eq!(details.status, BorrowStatus::Liquidation);
+        assert_eq!(details.health_factor, Some(Rational::new(10, 11)));
+        assert_eq!(details.maximum_borrow_amount, 0.into());
+        assert_eq!(details.maximum_collateral_withdrawal, 0.into());
+    }
+
+    proptest! {
+        #[test]
+        fn borrow_position_details_match_is_healthy(
+            collateral in any::<u64>(),
+            liability in any::<u64>(),
+            collateral_asset_price in (1..=u128::MAX, 1..=u128::MAX),
+            borrow_asset_price in (1..=u128::MAX, 1..=u128::MAX),
+        ) {
+            let configuration = sample_configuration();
+            let price = OraclePriceProof {
+                collateral_asset_price: Rational::new(
+                    collateral_asset_price.0,
+                    collateral_asset_price.1,
+                ),
+                borrow_asset_price: Rational::new(borrow_asset_price.0, borrow_asset_price.1),
+            };
+            let (collateral, liability) = (u128::from(collateral), u128::from(liability));
+            let details = configuration.borrow_position_details(
+                &borrow_position(collateral, liability),
+                price,
+                u128::MAX,
+                0,
+            );
+            let healthy = |collateral, liability| {
+                configuration.is_healthy(&borrow_position(collateral, liability), price)
+            };
+
+            let withdrawal = details.maximum_collateral_withdrawal.0;
+            if healthy(collateral, liability) {
+                prop_assert!(healthy(collateral - withdrawal, liability));
+            }
+            if withdrawal < collateral {
+                prop_assert!(!healthy(collateral - withdrawal - 1, liability));
+            }
+
+            let amount = details.maximum_borrow_amount.0;
+            if amount > 0 {
+                let fee = configuration.origination_fee.of(amount).unwrap();
+                prop_assert!(healthy(collateral, liability + amount + fee));
+            }
+        }
+
+        #[test]
+        fn is_healthy_matches_exact_arithmetic(
+            collateral in any::<u32>(),
+            liability in any::<u32>(),
+            collateral_asset_price in (any::<u32>(), 1..=u32::MAX),
+            borrow_asset_price in (any::<u32>(), 1..=u32::MAX),
+            minimum_collateral_ratio in (any::<u16>(), 1..=u16::MAX),
+        ) {
+            // Products of four 32-bit values fit in a `u128`.
+            let expected = u128::from(collateral)
+                * u128::from(collateral_asset_price.0)
+                * u128::from(borrow_asset_price.1)
+                * u128::from(minimum_collateral_ratio.1)
+                >= u128::from(liability)
+                    * u128::from(borrow_asset_price.0)
+                    * u128::from(collateral_asset_price.1)
+                    * u128::from(minimum_collateral_ratio.0);
+
+            prop_assert_eq!(
+                is_healthy(
+                    Rational::new(minimum_collateral_ratio.0, minimum_collateral_ratio.1),
+                    collateral.into(),
+                    liability.into(),
+                    Rational::new(collateral_asset_price.0.into(), collateral_asset_price.1.into()),
+                    Rational::new(borrow_asset_price.0.into(), borrow_asset_price.1.into()),
+                ),
+                expected,
+            );
+        }
+
+        #[test]
+        fn is_healthy_is_exact_across_u128_range(
+            collateral in any::<u128>(),
+            liability in any::<u128>(),
+            price in 1..=u128::MAX,
+            minimum_collateral_ratio in 1..=u16::MAX,
+        ) {
+            // collateral * price >= liability * minimum_collateral_ratio
+            let expected = widening_mul(collateral, price)
+                >= widening_mul(liability, u128::from(minimum_collateral_ratio));
+
+            prop_assert_eq!(
+                is_healthy(
+                    Rational::new(minimum_collateral_ratio, 1),
+                    collateral,
+                    liability,
+                    Rational::new(price, 1),
+                    Rational::new(1, 1),
+                ),
+                expected,
+            );
+        }
+
+        #[test]
+        fn is_healthy_is_monotonic(
+            collateral in any::<u128>(),
+            liability in any::<u128>(),
+            extra_collateral in any::<u128>(),
+            repaid in any::<u128>(),
+            collateral_asset_price in (1..=u128::MAX, 1..=u128::MAX),
+            borrow_asset_price in (1..=u128::MAX, 1..=u128::MAX),
+            minimum_collateral_ratio in (1..=u16::MAX, 1..=u16::MAX),
+        ) {
+            let minimum_collateral_ratio =
+                Rational::new(minimum_collateral_ratio.0, minimum_collateral_ratio.1);
+            let collateral_asset_price =
+                Rational::new(collateral_asset_price.0, collateral_asset_price.1);
+            let borrow_asset_price = Rational::new(borrow_asset_price.0, borrow_asset_price.1);
+            let healthy = |collateral, liability| {
+                is_healthy(
+                    minimum_collateral_ratio,
+                    collateral,
+                    liability,
+                    collateral_asset_price,
+                    borrow_asset_price,
+                )
+            };
+
+            prop_assert!(healthy(collateral, 0));
+            prop_assert!(!healthy(0, liability.max(1)));
+
+            if healthy(collateral, liability) {
+                prop_assert!(healthy(collateral.saturating_add(extra_collateral), liability));
+                prop_assert!(healthy(collateral, liability.saturating_sub(repaid)));
+            } else {
+                prop_assert!(!healthy(collateral.saturating_sub(extra_collateral), liability));
+                prop_assert!(!healthy(collateral, liability.saturating_add(repaid)));
+            }
+        }
+    }
 }
@@ -0,0 +1,172 @@
+use near_sdk::{json_types::U128, near, AccountId};
+
+use crate::{borrow::BorrowPosition, supply::SupplyPosition};
+
+use super::MarketAction;
+
+/// NEP-297 events emitted whenever the state of a market changes. Events
+/// carry the resulting position, so indexers do not need to replay the
+/// market's accounting. Supply shares additionally emit the standard NEP-141
+/// `ft_mint`, `ft_burn` and `ft_transfer` events.
+#[near(event_json(standard = "templar-market"))]
+pub enum MarketEvent<'a> {
+    #[event_version("1.0.0")]
+    SupplyDeposit {
+        account_id: &'a AccountId,
+        amount: U128,
+        supply_position: &'a SupplyPosition,
+    },
+    #[event_version("1.0.0")]
+    SupplyWithdrawal {
+        account_id: &'a AccountId,
+        amount: U128,
+        supply_position: &'a SupplyPosition,
+    },
+    /// The transfer to the supplier failed, and the withdrawal was undone,
+    /// including its fee.
+    #[event_version("1.0.0")]
+    SupplyWithdrawalReverted {
+        account_id: &'a AccountId,
+        amount: U128,
+        supply_position: &'a SupplyPosition,
+    },
+    #[event_version("1.0.0")]
+    SupplyCollateralAssetRewardsWithdrawal {
+        account_id: &'a AccountId,
+        amount: U128,
+        supply_position: &'a SupplyPosition,
+    },
+    /// `amount` includes rewards carried over from earlier distributions
+    /// that happened while there were no supply shares. If there are still
+    /// none, the whole amount is carried over again. Otherwise, it is added
+    /// to `borrow_asset_deposited`, raising the share price.
+    #[event_version("1.0.0")]
+    BorrowAssetRewardDistribution {
+        amount: U128,
+        borrow_asset_deposited: U128,
+        borrow_asset_undistributed_rewards: U128,
+    },
+    #[event_version("1.0.0")]
+    WithdrawalFee { amount: U128 },
+    /// A deposit through `ft_on_transfer` was refunded in full because
+    /// `action` is paused.
+    #[event_version("1.0.0")]
+    PausedDepositRefund {
+        account_id: &'a AccountId,
+        action: MarketAction,
+        amount: U128,
+    },
+    /// Replaces any earlier request from the same account.
+    #[event_version("1.0.0")]
+    WithdrawalRequest {
+        account_id: &'a AccountId,
+        amount: U128,
+    },
+    #[event_version("1.0.0")]
+    WithdrawalRequestCancel { account_id: &'a AccountId },
+    /// `remaining` stays at the head of the queue. The withdrawal itself is
+    /// reported by [`MarketEvent::SupplyWithdrawal`].
+    #[event_version("1.0.0")]
+    WithdrawalRequestFill {
+        account_id: &'a AccountId,
+        amount: U128,
+        remaining: U128,
+    },
+    #[event_version("1.0.0")]
+    CollateralDeposit {
+        account_id: &'a AccountId,
+        amount: U128,
+        borrow_position: &'a BorrowPosition,
+    },
+    #[event_version("1.0.0")]
+    CollateralWithdrawal {
+        account_id: &'a AccountId,
+        amount: U128,
+        borrow_position: &'a BorrowPosition,
+    },
+    /// `amount` is sent to the borrower, `liability` (including fees) is
+    /// added to the borrow position.
+    #[event_version("1.0.0")]
+    Borrow {
+        account_id: &'a AccountId,
+        amount: U128,
+        liability: U128,
+        borrow_position: &'a BorrowPosition,
+    },
+    /// The transfer to the borrower failed, and the borrow was undone.
+    #[event_version("1.0.0")]
+    BorrowReverted {
+        account_id: &'a AccountId,
+        amount: U128,
+        liability: U128,
+        borrow_position: &'a BorrowPosition,
+    },
+    #[event_version("1.0.0")]
+    Repay {
+        account_id: &'a AccountId,
+        amount: U128,
+        borrow_position: &'a BorrowPosition,
+    },
+    #[event_version("1.0.0")]
+    PartialLiquidation {
+        account_id: &'a AccountId,
+        liquidator_id: &'a AccountId,
+        repaid_amount: U128,
+        collateral_amount: U128,
+        borrow_position: &'a BorrowPosition,
+    },
+    #[event_version("1.0.0")]
+    FullLiquidation {
+        account_id: &'a AccountId,
+        liquidator_id: &'a AccountId,
+        recovered_amount: U128,
+        collateral_amount: U128,
+        borrow_position: &'a BorrowPosition,
+    },
+    /// The transfer of the collateral to the liquidator failed, and the
+    /// liquidation was undone.
+    #[event_version("1.0.0")]
+    LiquidationReverted {
+        account_id: &'a AccountId,
+        repaid_amount: U128,
+        collateral_amount: U128,
+        borrow_position: &'a BorrowPosition,
+    },
+    /// The liability left without collateral after a liquidation was
+    /// written off at the expense of suppliers.
+    #[event_version("1.0.0")]
+    BorrowPositionWriteOff {
+        account_id: &'a AccountId,
+        amount: U128,
+        borrow_position: &'a BorrowPosition,
+    },
+    #[event_version("1.0.0")]
+    LiquidationMargin {
+        liquidator_id: &'a AccountId,
+        supply_position_amount: U128,
+        liquidator_amount: U128,
+        protocol_amount: U128,
+    },
+    #[event_version("1.0.0")]
+    LiquidatorRewardsWithdrawal {
+        account_id: &'a AccountId,
+        amount: U128,
+        liquidator_rewards: U128,
+    },
+    #[event_version("1.0.0")]
+    LiquidatorRewardsWithdrawalReverted {
+        account_id: &'a AccountId,
+        amount: U128,
+        liquidator_rewards: U128,
+    },
+    #[event_version("1.0.0")]
+    ProtocolRewardsWithdrawal {
+        amount: U128,
+        protocol_rewards: U128,
+    },
+    #[event_version("1.0.0")]
+    ProtocolRewardsWithdrawalReverted {
+        amount: U128,
+        protocol_rewards: U128,
+    },
+}
@@ -4,11 +4,14 @@ use near_sdk::{
 };
 
 use crate::{
-    borrow::{BorrowPosition, BorrowStatus},
+    borrow::{BorrowPosition, BorrowPositionDetails, BorrowStatus},
     supply::SupplyPosition,
 };
 
-use super::{BorrowAssetMetrics, MarketConfiguration, OraclePriceProof};
+use super::{
+    BorrowAssetMetrics, InterestRates, MarketConfiguration, MarketConfigurationError,
+    OraclePriceProof,
+};
 
 // #[near_sdk::ext_contract(ext_market)]
 pub trait MarketExternalInterface {
@@ -17,8 +20,15 @@ pub trait MarketExternalInterface {
     // ========================
 
     fn get_configuration(&self) -> MarketConfiguration;
+    /// Returns `None` if `configuration` would be accepted by
+    /// initialization or a configuration update.
+    fn validate_configuration(
+        &self,
+        configuration: MarketConfiguration,
+    ) -> Option<MarketConfigurationError>;
     fn get_borrow_asset_metrics(&self) -> BorrowAssetMetrics;
     fn get_collateral_asset_balance(&self) -> U128;
+    fn get_interest_rates(&self) -> InterestRates;
 
     // TODO: Decide how to work with remote balances:
 
@@ -32,7 +42,7 @@ pub trait MarketExternalInterface {
     fn list_borrows(&self, offset: Option<U64>, count: Option<U64>) -> Vec<AccountId>;
     fn list_supplys(&self, offset: Option<U64>, count: Option<U64>) -> Vec<AccountId>;
 
-    /// This function does need to retrieve a "proof-of-price" from somewhere, e.g. oracle.
+    // This function does need to retrieve a "proof-of-price" from somewhere, e.g. oracle.
     // fn liquidate(&mut self, account_id: AccountId, meta: ()) -> ();
 
     // ==================
@@ -51,6 +61,13 @@ pub trait MarketExternalInterface {
         account_id: AccountId,
         oracle_price_proof: OraclePriceProof,
     ) -> Option<BorrowStatus>;
+    /// Like [`MarketExternalInterface::get_borrow_status`], the provided
+    /// price data is not validated.
+    fn get_borrow_position_details(
+        &self,
+        account_id: AccountId,
+        oracle_price_proof: OraclePriceProof,
+    ) -> Option<BorrowPositionDetails>;
     /// Works for both registered and unregistered accounts.
     fn get_collateral_asset_deposit_address_for(
         &self,
@@ -59,7 +76,11 @@ pub trait MarketExternalInterface {
     ) -> String;
 
     fn initialize_borrow(&mut self, borrow_asset_amount: U128, collateral_asset_amount: U128);
-    fn borrow(&mut self, amount: U128, oracle_price_proof: OraclePriceProof) -> PromiseOrValue<()>;
+    /// Prices are fetched from the configured price oracle.
+    fn borrow(&mut self, amount: U128) -> PromiseOrValue<()>;
+    /// The borrow position must remain healthy after the withdrawal. Prices
+    /// are fetched from the configured price oracle.
+    fn withdraw_collateral(&mut self, amount: U128) -> PromiseOrValue<()>;
 
     // ================
     // SUPPLY FUNCTIONS
@@ -70,20 +91,42 @@ pub trait MarketExternalInterface {
     // Required to implement NEP-141 FT token receiver to receive local fungible tokens.
     // ft_on_receive :: where msg = supply
 
+    // Supplying mints shares of the market's borrow asset deposits, which are
+    // also the market's NEP-141 token. Rewards raise the value of the shares.
+
     fn get_supply_position(&self, account_id: AccountId) -> Option<SupplyPosition>;
+    /// The number of supply shares that `amount` of the borrow asset would
+    /// mint, rounded down.
+    fn convert_to_shares(&self, amount: U128) -> U128;
+    /// The amount of the borrow asset that `shares` are worth, rounded down.
+    fn convert_to_assets(&self, shares: U128) -> U128;
 
+    /// `amount` is of the borrow asset, not shares.
     fn queue_withdrawal(&mut self, amount: U128);
     fn cancel_withdrawal(&mut self);
-    /// Auto-harvests yield.
-    fn process_next_withdrawal(&mut self);
-
+    /// Burns the shares that the withdrawn amount is worth.
+    ///
+    /// Partially fills the request at the head of the queue if the market
+    /// does not hold enough of the borrow asset; the remainder stays at the
+    /// head of the queue.
+    fn process_next_withdrawal(&mut self) -> PromiseOrValue<()>;
+
+    /// Deprecated: rewards raise the price of supply shares, so there is
+    /// nothing to harvest. Does nothing.
     fn harvest_yield(&mut self);
 
     // =================
     // REWARDS FUNCTIONS
     // =================
-    fn withdraw_supply_position_rewards(&mut self, amount: U128);
-    fn withdraw_liquidator_rewards(&mut self, amount: U128);
-    fn withdraw_protocol_rewards(&mut self, amount: U128);
+    /// Deprecated: rewards are withdrawn with the supply they accrue to,
+    /// through [`Self::queue_withdrawal`]. Always fails.
+    fn withdraw_supply_position_rewards(&mut self, amount: U128) -> PromiseOrValue<()>;
+    fn get_liquidator_rewards(&self, account_id: AccountId) -> U128;
+    fn get_protocol_rewards(&self) -> U128;
+    /// Withdraws the caller's share of liquidation proceeds.
+    fn withdraw_liquidator_rewards(&mut self, amount: U128) -> PromiseOrValue<()>;
+    /// Withdraws the protocol's share of liquidation proceeds to
+    /// `protocol_account_id`. Only callable by that account.
+    fn withdraw_protocol_rewards(&mut self, amount: U128) -> PromiseOrValue<()>;
     // fn withdraw_insurance_rewards(&mut self, amount: U128);
 }
@@ -1,23 +1,28 @@
-use near_sdk::{
-    collections::{TreeMap, UnorderedMap},
-    env, near, AccountId, BorshStorageKey, IntoStorageKey,
-};
+use near_contract_standards::fungible_token::events::{FtBurn, FtMint, FtTransfer};
+use near_sdk::{collections::UnorderedMap, env, near, AccountId, BorshStorageKey, IntoStorageKey};
 
 use crate::{
-    borrow::BorrowPosition, market::MarketConfiguration, supply::SupplyPosition,
+    borrow::{BorrowPosition, BorrowPositionDetails, BorrowStatus},
+    fee::{Fee, MS_PER_YEAR},
+    market::MarketConfiguration,
+    number::U256,
+    rational::Rational,
+    supply::SupplyPosition,
     withdrawal_queue::WithdrawalQueue,
 };
 
-use super::OraclePriceProof;
+use super::{BorrowAssetMetrics, InterestRates, MarketEvent, OraclePriceProof};
+
+/// Fixed-point precision of [`Market::borrow_index`].
+pub const BORROW_INDEX_PRECISION: u128 = 1_000_000_000_000_000_000;
 
 #[derive(BorshStorageKey)]
 #[near]
 enum StorageKey {
     SupplyPositions,
     BorrowPositions,
-    TotalBorrowAssetDepositedLog,
-    BorrowAssetRewardDistributionLog,
     WithdrawalQueue,
+    LiquidatorRewards,
 }
 
 #[near]
@@ -26,18 +31,38 @@ pub struct Market {
     pub configuration: MarketConfiguration,
     /// There are two different balance records for the borrow asset. The
     /// current balance is `borrow_asset_balance = borrow_asset_deposited -
-    /// <amount loaned out>`.
+    /// <amount loaned out>`. `borrow_asset_deposited` includes rewards
+    /// distributed to suppliers, and backs [`Market::supply_shares`].
     pub borrow_asset_deposited: u128,
-    /// The current amount of borrow asset under direct control of the market.
+    /// Total supply shares. Their price rises as rewards are distributed.
+    pub supply_shares: u128,
+    /// The current amount of borrow asset under direct control of the market
+    /// that can be lent out or withdrawn by suppliers. It excludes
+    /// [`Market::liquidator_rewards`] and [`Market::protocol_rewards`].
     pub borrow_asset_balance: u128,
     /// The current amount of collateral asset under direct control of the
     /// market.
     pub collateral_asset_balance: u128,
     pub supply_positions: UnorderedMap<AccountId, SupplyPosition>,
     pub borrow_positions: UnorderedMap<AccountId, BorrowPosition>,
-    pub total_borrow_asset_deposited_log: TreeMap<u64, u128>,
-    pub borrow_asset_reward_distribution_log: TreeMap<u64, u128>,
+    /// Rewards distributed while there were no supply shares. They are carried
+    /// over to the next distribution.
+    pub borrow_asset_undistributed_rewards: u128,
     pub withdrawal_queue: WithdrawalQueue,
+    /// Borrow asset owed to liquidators from their share of liquidation
+    /// proceeds. Held by the market, but kept out of `borrow_asset_balance`.
+    pub liquidator_rewards: UnorderedMap<AccountId, u128>,
+    /// Borrow asset owed to the protocol from its share of liquidation
+    /// proceeds. Held by the market, but kept out of `borrow_asset_balance`.
+    pub protocol_rewards: u128,
+    /// Growth of a borrow asset liability through interest since the market
+    /// was created, scaled by [`BORROW_INDEX_PRECISION`]. It accrues at the
+    /// rate for the utilization at the time, so it is updated before every
+    /// change to the utilization.
+    pub borrow_index: u128,
+    pub borrow_index_updated_at_block_timestamp_ms: u64,
+    /// Total liability of all borrow positions as of `borrow_index`.
+    pub borrow_asset_liability: u128,
 }
 
 impl Market {
@@ -56,89 +81,534 @@ impl Market {
             prefix: prefix.clone(),
             configuration,
             borrow_asset_deposited: 0,
+            supply_shares: 0,
             borrow_asset_balance: 0,
             collateral_asset_balance: 0,
             supply_positions: UnorderedMap::new(key!(SupplyPositions)),
             borrow_positions: UnorderedMap::new(key!(BorrowPositions)),
-            total_borrow_asset_deposited_log: TreeMap::new(key!(TotalBorrowAssetDepositedLog)),
-            borrow_asset_reward_distribution_log: TreeMap::new(key!(
-                BorrowAssetRewardDistributionLog
-            )),
+            borrow_asset_undistributed_rewards: 0,
             withdrawal_queue: WithdrawalQueue::new(key!(WithdrawalQueue)),
+            liquidator_rewards: UnorderedMap::new(key!(LiquidatorRewards)),
+            protocol_rewards: 0,
+            borrow_index: BORROW_INDEX_PRECISION,
+            borrow_index_updated_at_block_timestamp_ms: 0,
+            borrow_asset_liability: 0,
         }
     }
 
+    /// Includes interest accrued up to the current block.
     pub fn get_borrow_position(&self, account_id: &AccountId) -> Option<BorrowPosition> {
-        self.borrow_positions.get(account_id)
+        let mut borrow_position = self.borrow_positions.get(account_id)?;
+        let block_timestamp_ms = env::block_timestamp_ms();
+        let (borrow_index, _) = self.calculate_borrow_index(block_timestamp_ms);
+        let interest = self.calculate_borrow_position_interest(
+            &borrow_position,
+            borrow_index,
+            block_timestamp_ms,
+        );
+        borrow_position
+            .accrue_interest(interest, borrow_index, block_timestamp_ms)
+            .unwrap_or_else(|| env::panic_str("Borrow position borrow asset liability overflow"));
+        Some(borrow_position)
+    }
+
+    pub fn get_borrow_position_details(
+        &self,
+        account_id: &AccountId,
+        oracle_price_proof: OraclePriceProof,
+    ) -> Option<BorrowPositionDetails> {
+        let borrow_position = self.get_borrow_position(account_id)?;
+
+        Some(self.configuration.borrow_position_details(
+            &borrow_position,
+            oracle_price_proof,
+            self.get_borrow_asset_metrics().available.0,
+            env::block_timestamp_ms(),
+        ))
     }
 
     pub fn get_supply_position(&self, account_id: &AccountId) -> Option<SupplyPosition> {
         self.supply_positions.get(account_id)
     }
 
-    fn log_borrow_asset_deposited(&mut self, amount: u128) {
-        let block_height = env::block_height();
-        self.total_borrow_asset_deposited_log
-            .insert(&block_height, &amount);
+    /// The number of supply shares that `amount` of the borrow asset is
+    /// worth, rounded down.
+    pub fn convert_to_shares(&self, amount: u128) -> u128 {
+        convert(
+            amount,
+            self.supply_shares,
+            self.borrow_asset_deposited,
+            false,
+        )
+    }
+
+    /// The amount of the borrow asset that `shares` are worth, rounded down.
+    pub fn convert_to_assets(&self, shares: u128) -> u128 {
+        convert(
+            shares,
+            self.borrow_asset_deposited,
+            self.supply_shares,
+            false,
+        )
+    }
+
+    /// Whether `account_id` has anything left in the market: a non-empty
+    /// supply or borrow position, a queued withdrawal, or unwithdrawn
+    /// liquidator rewards.
+    pub fn has_open_positions(&self, account_id: &AccountId) -> bool {
+        self.supply_positions
+            .get(account_id)
+            .is_some_and(|supply_position| supply_position.exists())
+            || self
+                .borrow_positions
+                .get(account_id)
+                .is_some_and(|borrow_position| borrow_position.exists())
+            || self.withdrawal_queue.contains(account_id)
+            || self.liquidator_rewards.get(account_id).is_some()
+    }
+
+    /// Removes the supply and borrow positions of `account_id`, which must
+    /// be empty, freeing their storage.
+    pub fn remove_empty_positions(&mut self, account_id: &AccountId) {
+        if self.has_open_positions(account_id) {
+            env::panic_str("Account has open positions");
+        }
+
+        self.supply_positions.remove(account_id);
+        self.borrow_positions.remove(account_id);
     }
 
     fn record_borrow_asset_reward_distribution(&mut self, amount: u128) {
-        let block_height = env::block_height();
-        let mut distributed_in_block = self
-            .borrow_asset_reward_distribution_log
-            .get(&block_height)
-            .unwrap_or(0);
-        distributed_in_block += amount;
-        self.borrow_asset_reward_distribution_log
-            .insert(&block_height, &distributed_in_block);
+        let amount = amount
+            .checked_add(self.borrow_asset_undistributed_rewards)
+            .unwrap_or_else(|| env::panic_str("Borrow asset rewards overflow"));
+
+        if self.supply_shares == 0 {
+            self.borrow_asset_undistributed_rewards = amount;
+        } else {
+            self.borrow_asset_undistributed_rewards = 0;
+
+            // Raises the price of every supply share.
+            self.borrow_asset_deposited = self
+                .borrow_asset_deposited
+                .checked_add(amount)
+                .unwrap_or_else(|| env::panic_str("Borrow asset deposited overflow"));
+        }
+
+        MarketEvent::BorrowAssetRewardDistribution {
+            amount: amount.into(),
+            borrow_asset_deposited: self.borrow_asset_deposited.into(),
+            borrow_asset_undistributed_rewards: self.borrow_asset_undistributed_rewards.into(),
+        }
+        .emit();
+    }
+
+    /// Lowers the price of every supply share by `amount`, e.g. to write off
+    /// a loss. Rewards carried over are reduced first.
+    fn record_borrow_asset_loss(&mut self, amount: u128) {
+        let carried_over = amount.min(self.borrow_asset_undistributed_rewards);
+        self.borrow_asset_undistributed_rewards -= carried_over;
+
+        self.borrow_asset_deposited = self
+            .borrow_asset_deposited
+            .checked_sub(amount - carried_over)
+            .unwrap_or_else(|| env::panic_str("Borrow asset deposited underflow"));
+    }
+
+    /// The borrow index and the total liability of all borrow positions,
+    /// accrued up to `block_timestamp_ms` at the current utilization.
+    pub fn calculate_borrow_index(&self, block_timestamp_ms: u64) -> (u128, u128) {
+        let duration_ms =
+            block_timestamp_ms.saturating_sub(self.borrow_index_updated_at_block_timestamp_ms);
+
+        if duration_ms == 0 || self.borrow_asset_liability == 0 {
+            return (self.borrow_index, self.borrow_asset_liability);
+        }
+
+        let rate = self
+            .configuration
+            .interest_rate_model
+            .borrow_rate(self.borrow_asset_used(), self.borrow_asset_supplied())
+            .unwrap_or_else(|| env::panic_str("Interest rate calculation failed"));
+        // A flat maintenance fee does not depend on the liability, so it is
+        // charged per position instead.
+        let maintenance_rate = match self.configuration.annual_maintenance_fee {
+            Fee::Flat(_) => Rational::new(0, 1),
+            Fee::Proportional(rate) => rate.upcast(),
+        };
+
+        // index * (rate + maintenance_rate) * duration / year, rounded up.
+        let numerator = U256::from(rate.numerator()) * U256::from(maintenance_rate.denominator())
+            + U256::from(maintenance_rate.numerator()) * U256::from(rate.denominator());
+        let denominator = U256::from(rate.denominator())
+            * U256::from(maintenance_rate.denominator())
+            * U256::from(MS_PER_YEAR);
+        let growth = div_ceil(
+            U256::from(self.borrow_index) * numerator * U256::from(duration_ms),
+            denominator,
+        );
+
+        let borrow_index = u128::try_from(U256::from(self.borrow_index) + growth)
+            .unwrap_or_else(|_| env::panic_str("Borrow index overflow"));
+        // Rounded down, so that suppliers are never credited more than the
+        // positions owe.
+        let liability = u128::try_from(
+            U256::from(self.borrow_asset_liability) * U256::from(borrow_index)
+                / U256::from(self.borrow_index),
+        )
+        .unwrap_or_else(|_| env::panic_str("Borrow asset liability overflow"));
+
+        (borrow_index, liability)
+    }
+
+    /// Interest on `borrow_position` since it was last accrued, given the
+    /// current `borrow_index`: the growth of the index, and the flat
+    /// maintenance fee, if any.
+    pub fn calculate_borrow_position_interest(
+        &self,
+        borrow_position: &BorrowPosition,
+        borrow_index: u128,
+        block_timestamp_ms: u64,
+    ) -> u128 {
+        self.calculate_borrow_position_index_interest(borrow_position, borrow_index)
+            .checked_add(
+                self.calculate_borrow_position_flat_fee(borrow_position, block_timestamp_ms),
+            )
+            .unwrap_or_else(|| env::panic_str("Interest calculation failed"))
+    }
+
+    fn calculate_borrow_position_index_interest(
+        &self,
+        borrow_position: &BorrowPosition,
+        borrow_index: u128,
+    ) -> u128 {
+        let liability = borrow_position.borrow_asset_liability.0;
+        let position_index = borrow_position.borrow_index.0;
+
+        if liability == 0 || position_index == 0 {
+            return 0;
+        }
+
+        // Rounded up, so that rounding never favors the borrower.
+        let accrued = div_ceil(
+            U256::from(liability) * U256::from(borrow_index),
+            U256::from(position_index),
+        );
+
+        u128::try_from(accrued - U256::from(liability))
+            .unwrap_or_else(|_| env::panic_str("Interest calculation failed"))
+    }
+
+    fn calculate_borrow_position_flat_fee(
+        &self,
+        borrow_position: &BorrowPosition,
+        block_timestamp_ms: u64,
+    ) -> u128 {
+        let Fee::Flat(_) = self.configuration.annual_maintenance_fee else {
+            return 0;
+        };
+
+        if borrow_position.borrow_asset_liability.0 == 0 {
+            return 0;
+        }
+
+        let duration_ms =
+            block_timestamp_ms.saturating_sub(borrow_position.last_accrued_at_block_timestamp_ms.0);
+
+        self.configuration
+            .annual_maintenance_fee
+            .of_prorated(borrow_position.borrow_asset_liability.0, duration_ms)
+            .unwrap_or_else(|| env::panic_str("Interest calculation failed"))
+    }
+
+    /// Accrues interest on all borrow positions up to the current block, and
+    /// distributes it to suppliers. Called before anything that changes the
+    /// utilization, so that every period accrues at its own rate.
+    pub fn accrue_borrow_interest(&mut self) {
+        let block_timestamp_ms = env::block_timestamp_ms();
+        let (borrow_index, liability) = self.calculate_borrow_index(block_timestamp_ms);
+        let interest = liability - self.borrow_asset_liability;
+
+        self.borrow_index = borrow_index;
+        self.borrow_asset_liability = liability;
+        self.borrow_index_updated_at_block_timestamp_ms = block_timestamp_ms;
+
+        if interest > 0 {
+            self.record_borrow_asset_reward_distribution(interest);
+        }
+    }
+
+    fn increase_borrow_asset_liability(&mut self, amount: u128) {
+        self.borrow_asset_liability = self
+            .borrow_asset_liability
+            .checked_add(amount)
+            .unwrap_or_else(|| env::panic_str("Borrow asset liability overflow"));
+    }
+
+    fn decrease_borrow_asset_liability(&mut self, amount: u128) {
+        // Positions round their interest up and the total rounds it down, so
+        // the positions may owe slightly more than the total. The excess is
+        // interest that was not distributed yet.
+        if let Some(excess) = amount.checked_sub(self.borrow_asset_liability) {
+            self.borrow_asset_liability = 0;
+            if excess > 0 {
+                self.record_borrow_asset_reward_distribution(excess);
+            }
+        } else {
+            self.borrow_asset_liability -= amount;
+        }
     }
 
+    /// Borrow asset owed to suppliers, including rewards carried over until
+    /// there are supply shares again.
+    fn borrow_asset_supplied(&self) -> u128 {
+        self.borrow_asset_deposited
+            .checked_add(self.borrow_asset_undistributed_rewards)
+            .unwrap_or_else(|| env::panic_str("Borrow asset deposited overflow"))
+    }
+
+    /// Amount of the borrow asset that is currently lent out. It equals the
+    /// total liability of all borrow positions.
+    pub fn borrow_asset_used(&self) -> u128 {
+        self.borrow_asset_supplied()
+            .checked_sub(self.borrow_asset_balance)
+            .unwrap_or_else(|| env::panic_str("Borrow asset balance exceeds deposits"))
+    }
+
+    pub fn get_borrow_asset_metrics(&self) -> BorrowAssetMetrics {
+        BorrowAssetMetrics::calculate(
+            self.borrow_asset_supplied(),
+            self.borrow_asset_balance,
+            self.configuration.maximum_borrow_asset_usage_ratio.upcast(),
+        )
+    }
+
+    pub fn get_interest_rates(&self) -> InterestRates {
+        let used = self.borrow_asset_used();
+        let model = &self.configuration.interest_rate_model;
+
+        InterestRates {
+            borrow_apr: model
+                .borrow_rate(used, self.borrow_asset_supplied())
+                .unwrap_or_else(|| env::panic_str("Interest rate calculation failed")),
+            supply_apy: model
+                .supply_rate(used, self.borrow_asset_supplied())
+                .unwrap_or_else(|| env::panic_str("Interest rate calculation failed")),
+        }
+    }
+
+    /// Loads a borrow position and accrues interest on it up to the current
+    /// block. The caller is responsible for writing the position back to
+    /// storage.
+    fn accrue_borrow_position_interest(&mut self, account_id: &AccountId) -> BorrowPosition {
+        self.accrue_borrow_interest();
+
+        let mut borrow_position = self.borrow_positions.get(account_id).unwrap_or_default();
+        let block_timestamp_ms = env::block_timestamp_ms();
+        let interest =
+            self.calculate_borrow_position_index_interest(&borrow_position, self.borrow_index);
+        let flat_fee =
+            self.calculate_borrow_position_flat_fee(&borrow_position, block_timestamp_ms);
+        let total_interest = interest
+            .checked_add(flat_fee)
+            .unwrap_or_else(|| env::panic_str("Interest calculation failed"));
+
+        borrow_position
+            .accrue_interest(total_interest, self.borrow_index, block_timestamp_ms)
+            .unwrap_or_else(|| env::panic_str("Borrow position borrow asset liability overflow"));
+
+        // Interest from the borrow index is already included in the total
+        // and distributed.
+        if flat_fee > 0 {
+            self.increase_borrow_asset_liability(flat_fee);
+            self.record_borrow_asset_reward_distribution(flat_fee);
+        }
+
+        borrow_position
+    }
+
+    /// Mints supply shares for `amount` at the current share price.
     pub fn record_supply_position_borrow_asset_deposit(
         &mut self,
         account_id: &AccountId,
         amount: u128,
     ) {
+        self.accrue_borrow_interest();
+
+        let shares = self.convert_to_shares(amount);
+        if shares == 0 {
+            env::panic_str("Supply amount is too small to mint any shares");
+        }
+
         let mut supply_position = self
             .supply_positions
             .get(account_id)
             .unwrap_or_else(|| SupplyPosition::new(env::block_height()));
 
         supply_position
-            .deposit_borrow_asset(amount)
-            .unwrap_or_else(|| env::panic_str("Supply position borrow asset overflow"));
+            .deposit_shares(shares, env::block_timestamp_ms())
+            .unwrap_or_else(|| env::panic_str("Supply position shares overflow"));
 
         self.supply_positions.insert(account_id, &supply_position);
 
+        FtMint {
+            owner_id: account_id,
+            amount: shares.into(),
+            memo: None,
+        }
+        .emit();
+
+        MarketEvent::SupplyDeposit {
+            account_id,
+            amount: amount.into(),
+            supply_position: &supply_position,
+        }
+        .emit();
+
+        self.supply_shares = self
+            .supply_shares
+            .checked_add(shares)
+            .unwrap_or_else(|| env::panic_str("Supply shares overflow"));
+
         self.borrow_asset_deposited = self
             .borrow_asset_deposited
             .checked_add(amount)
             .unwrap_or_else(|| env::panic_str("Borrow asset deposited overflow"));
 
-        self.log_borrow_asset_deposited(self.borrow_asset_deposited);
+        self.borrow_asset_balance = self
+            .borrow_asset_balance
+            .checked_add(amount)
+            .unwrap_or_else(|| env::panic_str("Borrow asset balance overflow"));
     }
 
+    /// Burns the supply shares that `amount` is worth, rounded up. Returns
+    /// the number of shares burned.
     pub fn record_supply_position_borrow_asset_withdrawal(
         &mut self,
         account_id: &AccountId,
         amount: u128,
-    ) {
+    ) -> u128 {
+        self.accrue_borrow_interest();
+
+        let shares = convert(
+            amount,
+            self.supply_shares,
+            self.borrow_asset_deposited,
+            true,
+        );
+
         let mut supply_position = self
             .supply_positions
             .get(account_id)
             .unwrap_or_else(|| SupplyPosition::new(env::block_height()));
 
         supply_position
-            .withdraw_borrow_asset(amount)
-            .unwrap_or_else(|| env::panic_str("Supply position borrow asset underflow"));
+            .withdraw_shares(shares)
+            .unwrap_or_else(|| env::panic_str("Supply position shares underflow"));
 
         self.supply_positions.insert(account_id, &supply_position);
 
+        FtBurn {
+            owner_id: account_id,
+            amount: shares.into(),
+            memo: None,
+        }
+        .emit();
+
+        MarketEvent::SupplyWithdrawal {
+            account_id,
+            amount: amount.into(),
+            supply_position: &supply_position,
+        }
+        .emit();
+
+        self.supply_shares = self
+            .supply_shares
+            .checked_sub(shares)
+            .unwrap_or_else(|| env::panic_str("Supply shares underflow"));
+
         self.borrow_asset_deposited = self
             .borrow_asset_deposited
             .checked_sub(amount)
             .unwrap_or_else(|| env::panic_str("Borrow asset deposited underflow"));
 
-        self.log_borrow_asset_deposited(self.borrow_asset_deposited);
+        self.borrow_asset_balance = self
+            .borrow_asset_balance
+            .checked_sub(amount)
+            .unwrap_or_else(|| env::panic_str("Borrow asset balance underflow"));
+
+        shares
+    }
+
+    /// Reverses [`Market::record_supply_position_borrow_asset_withdrawal`],
+    /// e.g. when the transfer to the supplier fails. The burned `shares` are
+    /// minted again, and keep the start they had before the withdrawal.
+    pub fn revert_supply_position_borrow_asset_withdrawal(
+        &mut self,
+        account_id: &AccountId,
+        amount: u128,
+        shares: u128,
+        started_at_block_timestamp_ms: u64,
+    ) {
+        self.accrue_borrow_interest();
+
+        let mut supply_position = self
+            .supply_positions
+            .get(account_id)
+            .unwrap_or_else(|| SupplyPosition::new(env::block_height()));
+
+        supply_position
+            .deposit_shares(shares, started_at_block_timestamp_ms)
+            .unwrap_or_else(|| env::panic_str("Supply position shares overflow"));
+
+        self.supply_positions.insert(account_id, &supply_position);
+
+        FtMint {
+            owner_id: account_id,
+            amount: shares.into(),
+            memo: None,
+        }
+        .emit();
+
+        MarketEvent::SupplyWithdrawalReverted {
+            account_id,
+            amount: amount.into(),
+            supply_position: &supply_position,
+        }
+        .emit();
+
+        self.supply_shares = self
+            .supply_shares
+            .checked_add(shares)
+            .unwrap_or_else(|| env::panic_str("Supply shares overflow"));
+
+        self.borrow_asset_deposited = self
+            .borrow_asset_deposited
+            .checked_add(amount)
+            .unwrap_or_else(|| env::panic_str("Borrow asset deposited overflow"));
+
+        self.borrow_asset_balance = self
+            .borrow_asset_balance
+            .checked_add(amount)
+            .unwrap_or_else(|| env::panic_str("Borrow asset balance overflow"));
+    }
+
+    /// Withdrawal fees are deducted from the amount sent to the supplier, so
+    /// they never leave the market. They are distributed to the suppliers.
+    pub fn record_borrow_asset_withdrawal_fee(&mut self, amount: u128) {
+        self.accrue_borrow_interest();
+
+        MarketEvent::WithdrawalFee {
+            amount: amount.into(),
+        }
+        .emit();
+
+        self.borrow_asset_balance = self
+            .borrow_asset_balance
+            .checked_add(amount)
+            .unwrap_or_else(|| env::panic_str("Borrow asset balance overflow"));
+
+        self.record_borrow_asset_reward_distribution(amount);
     }
 
     pub fn record_borrow_position_collateral_asset_deposit(
@@ -146,7 +616,7 @@ impl Market {
         account_id: &AccountId,
         amount: u128,
     ) {
-        let mut borrow_position = self.borrow_positions.get(account_id).unwrap_or_default();
+        let mut borrow_position = self.accrue_borrow_position_interest(account_id);
 
         borrow_position
             .increase_collateral_asset_deposit(amount)
@@ -154,6 +624,13 @@ impl Market {
 
         self.borrow_positions.insert(account_id, &borrow_position);
 
+        MarketEvent::CollateralDeposit {
+            account_id,
+            amount: amount.into(),
+            borrow_position: &borrow_position,
+        }
+        .emit();
+
         self.collateral_asset_balance = self
             .collateral_asset_balance
             .checked_add(amount)
@@ -164,8 +641,8 @@ impl Market {
         &mut self,
         account_id: &AccountId,
         amount: u128,
-    ) {
-        let mut borrow_position = self.borrow_positions.get(account_id).unwrap_or_default();
+    ) -> BorrowPosition {
+        let mut borrow_position = self.accrue_borrow_position_interest(account_id);
 
         borrow_position
             .decrease_collateral_asset_deposit(amount)
@@ -173,10 +650,19 @@ impl Market {
 
         self.borrow_positions.insert(account_id, &borrow_position);
 
+        MarketEvent::CollateralWithdrawal {
+            account_id,
+            amount: amount.into(),
+            borrow_position: &borrow_position,
+        }
+        .emit();
+
         self.collateral_asset_balance = self
             .collateral_asset_balance
             .checked_sub(amount)
             .unwrap_or_else(|| env::panic_str("Collateral asset balance underflow"));
+
+        borrow_position
     }
 
     pub fn record_borrow_position_borrow_asset_withdrawal(
@@ -185,14 +671,32 @@ impl Market {
         liable_amount: u128,
         dispersed_amount: u128,
     ) -> BorrowPosition {
-        let mut borrow_position = self.borrow_positions.get(account_id).unwrap_or_default();
+        let mut borrow_position = self.accrue_borrow_position_interest(account_id);
 
         borrow_position
             .increase_borrow_asset_liability(liable_amount)
             .unwrap_or_else(|| env::panic_str("Borrow position borrow asset liability overflow"));
+        borrow_position.start_borrow(env::block_timestamp_ms());
+        self.increase_borrow_asset_liability(liable_amount);
 
         self.borrow_positions.insert(account_id, &borrow_position);
 
+        // The origination fee is owed to suppliers like interest.
+        let fee = liable_amount
+            .checked_sub(dispersed_amount)
+            .unwrap_or_else(|| env::panic_str("Borrow liability is less than the amount"));
+        if fee > 0 {
+            self.record_borrow_asset_reward_distribution(fee);
+        }
+
+        MarketEvent::Borrow {
+            account_id,
+            amount: dispersed_amount.into(),
+            liability: liable_amount.into(),
+            borrow_position: &borrow_position,
+        }
+        .emit();
+
         self.borrow_asset_balance = self
             .borrow_asset_balance
             .checked_sub(dispersed_amount)
@@ -201,23 +705,124 @@ impl Market {
         borrow_position
     }
 
+    /// Reverses [`Market::record_borrow_position_borrow_asset_withdrawal`],
+    /// e.g. when the transfer to the borrower fails.
+    pub fn revert_borrow_position_borrow_asset_withdrawal(
+        &mut self,
+        account_id: &AccountId,
+        liable_amount: u128,
+        dispersed_amount: u128,
+    ) {
+        let mut borrow_position = self.accrue_borrow_position_interest(account_id);
+
+        borrow_position
+            .decrease_borrow_asset_liability(liable_amount)
+            .unwrap_or_else(|| env::panic_str("Borrow position borrow asset liability underflow"));
+        self.decrease_borrow_asset_liability(liable_amount);
+
+        self.borrow_positions.insert(account_id, &borrow_position);
+
+        // Takes back the origination fee distributed with the borrow.
+        self.record_borrow_asset_loss(liable_amount - dispersed_amount);
+
+        MarketEvent::BorrowReverted {
+            account_id,
+            amount: dispersed_amount.into(),
+            liability: liable_amount.into(),
+            borrow_position: &borrow_position,
+        }
+        .emit();
+
+        self.borrow_asset_balance = self
+            .borrow_asset_balance
+            .checked_add(dispersed_amount)
+            .unwrap_or_else(|| env::panic_str("Borrow asset balance overflow"));
+    }
+
+    /// Repays at most the position's liability. Returns the amount repaid.
     pub fn record_borrow_position_borrow_asset_repay(
         &mut self,
         account_id: &AccountId,
         amount: u128,
-    ) {
-        let mut borrow_position = self.borrow_positions.get(account_id).unwrap_or_default();
+    ) -> u128 {
+        let mut borrow_position = self.accrue_borrow_position_interest(account_id);
+
+        if !borrow_position.exists() {
+            return 0;
+        }
+
+        let amount = amount.min(borrow_position.borrow_asset_liability.0);
 
         borrow_position
             .decrease_borrow_asset_liability(amount)
             .unwrap_or_else(|| env::panic_str("Borrow position borrow asset liability underflow"));
+        self.decrease_borrow_asset_liability(amount);
 
         self.borrow_positions.insert(account_id, &borrow_position);
 
+        MarketEvent::Repay {
+            account_id,
+            amount: amount.into(),
+            borrow_position: &borrow_position,
+        }
+        .emit();
+
         self.borrow_asset_balance = self
             .borrow_asset_balance
             .checked_add(amount)
             .unwrap_or_else(|| env::panic_str("Total loan asset borrowed underflow"));
+
+        amount
+    }
+
+    /// Moves supply shares, e.g. for an NEP-141 transfer. The share price
+    /// is unaffected. The receiver's start is averaged with the sender's, as
+    /// in [`SupplyPosition::deposit_shares`].
+    pub fn record_supply_shares_transfer(
+        &mut self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        shares: u128,
+        memo: Option<&str>,
+    ) {
+        if sender_id == receiver_id {
+            env::panic_str("Sender and receiver should be different");
+        }
+        if shares == 0 {
+            env::panic_str("The amount should be a positive number");
+        }
+
+        let mut sender_position = self
+            .supply_positions
+            .get(sender_id)
+            .unwrap_or_else(|| SupplyPosition::new(env::block_height()));
+        // The shares keep their age, so that transfers neither reset nor
+        // avoid the withdrawal fee period.
+        let acquired_at_block_timestamp_ms = sender_position
+            .started_at_block_timestamp_ms
+            .map_or_else(env::block_timestamp_ms, |started_at| started_at.0);
+        sender_position
+            .withdraw_shares(shares)
+            .unwrap_or_else(|| env::panic_str("Supply position shares underflow"));
+        self.supply_positions.insert(sender_id, &sender_position);
+
+        let mut receiver_position = self
+            .supply_positions
+            .get(receiver_id)
+            .unwrap_or_else(|| SupplyPosition::new(env::block_height()));
+        receiver_position
+            .deposit_shares(shares, acquired_at_block_timestamp_ms)
+            .unwrap_or_else(|| env::panic_str("Supply position shares overflow"));
+        self.supply_positions
+            .insert(receiver_id, &receiver_position);
+
+        FtTransfer {
+            old_owner_id: sender_id,
+            new_owner_id: receiver_id,
+            amount: shares.into(),
+            memo,
+        }
+        .emit();
     }
 
     pub fn record_supply_position_collateral_rewards_withdrawal(
@@ -238,103 +843,853 @@ impl Market {
             });
 
         self.supply_positions.insert(account_id, &supply_position);
-    }
-
-    pub fn calculate_supply_position_rewards(
-        &self,
-        reward_distribution_log: &TreeMap<u64, u128>,
-        last_updated_block_height: u64,
-        deposit_during_interval: u128,
-        until_block_height: u64,
-    ) -> (u128, u64) {
-        let start_from_block_height = reward_distribution_log
-            .floor_key(&last_updated_block_height)
-            .unwrap()
-            - 1; // -1 because TreeMap::iter_from start is _exclusive_
 
-        // We explicitly want to _exclude_ `until_block_height` because the
-        // intended use of this method is that it will be
-        // `env::block_height()`, and in this case, it would be possible for us
-        // to miss some rewards if they were distributed in the same block but
-        // after this function call.
-        if start_from_block_height >= until_block_height {
-            return (0, last_updated_block_height);
+        MarketEvent::SupplyCollateralAssetRewardsWithdrawal {
+            account_id,
+            amount: amount.into(),
+            supply_position: &supply_position,
         }
+        .emit();
+    }
 
-        let mut accumulated_fees_in_span = 0;
-        let mut last_block_height = start_from_block_height;
+    pub fn can_borrow_position_be_liquidated(
+        &self,
+        account_id: &AccountId,
+        oracle_price_proof: OraclePriceProof,
+    ) -> bool {
+        let Some(borrow_position) = self.get_borrow_position(account_id) else {
+            return false;
+        };
 
-        for (block_height, fees) in reward_distribution_log.iter_from(start_from_block_height) {
-            if block_height >= until_block_height {
-                break;
-            }
+        self.configuration.borrow_status(
+            &borrow_position,
+            oracle_price_proof,
+            env::block_timestamp_ms(),
+        ) != BorrowStatus::Healthy
+    }
 
-            let total_loan_asset_deposited_at_distribution = self
-                .total_borrow_asset_deposited_log
-                .get(
-                    &self
-                        .total_borrow_asset_deposited_log
-                        .floor_key(&block_height)
-                        .unwrap(),
-                )
-                .unwrap();
+    pub fn record_partial_liquidation(
+        &mut self,
+        account_id: &AccountId,
+        liquidator_id: &AccountId,
+        repaid_borrow_asset_amount: u128,
+        liquidated_collateral_asset_amount: u128,
+    ) {
+        let mut borrow_position = self.accrue_borrow_position_interest(account_id);
 
-            // this discards fractional fees
-            let portion_of_fees = fees
-                .checked_mul(deposit_during_interval)
-                .unwrap()
-                .checked_div(total_loan_asset_deposited_at_distribution)
-                .unwrap();
+        borrow_position
+            .decrease_collateral_asset_deposit(liquidated_collateral_asset_amount)
+            .unwrap_or_else(|| env::panic_str("Borrow position collateral asset underflow"));
+        borrow_position
+            .decrease_borrow_asset_liability(repaid_borrow_asset_amount)
+            .unwrap_or_else(|| env::panic_str("Borrow position borrow asset liability underflow"));
+        self.decrease_borrow_asset_liability(repaid_borrow_asset_amount);
 
-            accumulated_fees_in_span += portion_of_fees;
+        self.borrow_positions.insert(account_id, &borrow_position);
 
-            last_block_height = block_height;
+        MarketEvent::PartialLiquidation {
+            account_id,
+            liquidator_id,
+            repaid_amount: repaid_borrow_asset_amount.into(),
+            collateral_amount: liquidated_collateral_asset_amount.into(),
+            borrow_position: &borrow_position,
         }
+        .emit();
 
-        (accumulated_fees_in_span, last_block_height)
+        self.collateral_asset_balance = self
+            .collateral_asset_balance
+            .checked_sub(liquidated_collateral_asset_amount)
+            .unwrap_or_else(|| env::panic_str("Collateral asset balance underflow"));
+
+        self.borrow_asset_balance = self
+            .borrow_asset_balance
+            .checked_add(repaid_borrow_asset_amount)
+            .unwrap_or_else(|| env::panic_str("Borrow asset balance overflow"));
     }
 
-    pub fn can_borrow_position_be_liquidated(
-        &self,
+    /// Reverses [`Market::record_partial_liquidation`] or
+    /// [`Market::record_full_liquidation`], e.g. when the transfer of the
+    /// collateral to the liquidator fails. `repaid_borrow_asset_amount`
+    /// excludes the margin, which is only recorded once the transfer
+    /// succeeds. The borrow keeps the start it had before the liquidation.
+    pub fn revert_liquidation(
+        &mut self,
         account_id: &AccountId,
-        oracle_price_proof: OraclePriceProof,
-    ) -> bool {
-        let Some(borrow_position) = self.borrow_positions.get(account_id) else {
-            return false;
-        };
+        repaid_borrow_asset_amount: u128,
+        liquidated_collateral_asset_amount: u128,
+        started_at_block_timestamp_ms: Option<u64>,
+    ) {
+        let mut borrow_position = self.accrue_borrow_position_interest(account_id);
 
-        !self
-            .configuration
-            .is_healthy(&borrow_position, oracle_price_proof)
+        borrow_position
+            .increase_collateral_asset_deposit(liquidated_collateral_asset_amount)
+            .unwrap_or_else(|| env::panic_str("Borrow position collateral asset overflow"));
+        borrow_position
+            .increase_borrow_asset_liability(repaid_borrow_asset_amount)
+            .unwrap_or_else(|| env::panic_str("Borrow position borrow asset liability overflow"));
+        self.increase_borrow_asset_liability(repaid_borrow_asset_amount);
+        borrow_position.started_at_block_timestamp_ms =
+            started_at_block_timestamp_ms.map(Into::into);
+
+        self.borrow_positions.insert(account_id, &borrow_position);
+
+        MarketEvent::LiquidationReverted {
+            account_id,
+            repaid_amount: repaid_borrow_asset_amount.into(),
+            collateral_amount: liquidated_collateral_asset_amount.into(),
+            borrow_position: &borrow_position,
+        }
+        .emit();
+
+        self.collateral_asset_balance = self
+            .collateral_asset_balance
+            .checked_add(liquidated_collateral_asset_amount)
+            .unwrap_or_else(|| env::panic_str("Collateral asset balance overflow"));
+
+        self.borrow_asset_balance = self
+            .borrow_asset_balance
+            .checked_sub(repaid_borrow_asset_amount)
+            .unwrap_or_else(|| env::panic_str("Borrow asset balance underflow"));
     }
 
+    /// Applies `recovered_borrow_asset_amount` to the liability. Returns the
+    /// part of it beyond the liability, which is to be recorded with
+    /// [`Market::record_liquidation_margin`] once the liquidation is final.
     pub fn record_full_liquidation(
         &mut self,
         account_id: &AccountId,
+        liquidator_id: &AccountId,
         recovered_borrow_asset_amount: u128,
-    ) {
-        let mut borrow_position = self.borrow_positions.get(account_id).unwrap_or_default();
+    ) -> u128 {
+        let mut borrow_position = self.accrue_borrow_position_interest(account_id);
 
         let collateral_asset_amount_liquidated =
             borrow_position.zero_out_collateral_asset_deposit();
 
-        // TODO: bounds checks
-        self.collateral_asset_balance -= collateral_asset_amount_liquidated;
-        self.borrow_asset_balance += recovered_borrow_asset_amount;
-
-        if let Some(margin) =
+        let margin = if let Some(margin) =
             recovered_borrow_asset_amount.checked_sub(borrow_position.borrow_asset_liability.0)
         {
-            // distribute rewards
-            self.record_borrow_asset_reward_distribution(margin);
+            let liability = borrow_position.zero_out_borrow_asset_liability();
+            self.decrease_borrow_asset_liability(liability);
+            margin
         } else {
-            // we took a loss
-            // TODO: some sort of recovery for suppliers
+            // We took a loss. The rest of the liability is left without
+            // collateral, and is written off with
+            // `record_borrow_position_write_off` once the liquidation is final.
             borrow_position
                 .decrease_borrow_asset_liability(recovered_borrow_asset_amount)
-                .unwrap();
+                .unwrap_or_else(|| {
+                    env::panic_str("Borrow position borrow asset liability underflow")
+                });
+            self.decrease_borrow_asset_liability(recovered_borrow_asset_amount);
+            0
+        };
+
+        self.collateral_asset_balance = self
+            .collateral_asset_balance
+            .checked_sub(collateral_asset_amount_liquidated)
+            .unwrap_or_else(|| env::panic_str("Collateral asset balance underflow"));
+
+        self.borrow_asset_balance = self
+            .borrow_asset_balance
+            .checked_add(recovered_borrow_asset_amount - margin)
+            .unwrap_or_else(|| env::panic_str("Borrow asset balance overflow"));
+
+        self.borrow_positions.insert(account_id, &borrow_position);
+
+        MarketEvent::FullLiquidation {
+            account_id,
+            liquidator_id,
+            recovered_amount: recovered_borrow_asset_amount.into(),
+            collateral_amount: collateral_asset_amount_liquidated.into(),
+            borrow_position: &borrow_position,
         }
+        .emit();
+
+        margin
+    }
+
+    /// Writes off the liability of a borrow position that has no collateral
+    /// left, e.g. after a full liquidation at a loss, so that it stops
+    /// accruing interest. Suppliers bear the loss. Returns the amount
+    /// written off.
+    pub fn record_borrow_position_write_off(&mut self, account_id: &AccountId) -> u128 {
+        let mut borrow_position = self.accrue_borrow_position_interest(account_id);
+
+        if borrow_position.collateral_asset_deposit.0 > 0
+            || borrow_position.borrow_asset_liability.0 == 0
+        {
+            return 0;
+        }
+
+        let liability = borrow_position.zero_out_borrow_asset_liability();
+        self.decrease_borrow_asset_liability(liability);
 
         self.borrow_positions.insert(account_id, &borrow_position);
+
+        MarketEvent::BorrowPositionWriteOff {
+            account_id,
+            amount: liability.into(),
+            borrow_position: &borrow_position,
+        }
+        .emit();
+
+        self.record_borrow_asset_loss(liability);
+
+        liability
+    }
+
+    /// Records the margin paid by a liquidator for the liquidation bonus,
+    /// splitting it according to the configured
+    /// [`LiquidationSpread`](super::LiquidationSpread).
+    pub fn record_liquidation_margin(&mut self, liquidator_id: &AccountId, margin: u128) {
+        if margin == 0 {
+            return;
+        }
+
+        self.accrue_borrow_interest();
+
+        let amounts = self.configuration.liquidation_spread.split(margin);
+
+        // Only the suppliers' share can be lent out or withdrawn by them.
+        self.borrow_asset_balance = self
+            .borrow_asset_balance
+            .checked_add(amounts.supply_position)
+            .unwrap_or_else(|| env::panic_str("Borrow asset balance overflow"));
+
+        MarketEvent::LiquidationMargin {
+            liquidator_id,
+            supply_position_amount: amounts.supply_position.into(),
+            liquidator_amount: amounts.liquidator.into(),
+            protocol_amount: amounts.protocol.into(),
+        }
+        .emit();
+
+        self.record_borrow_asset_reward_distribution(amounts.supply_position);
+
+        if amounts.liquidator > 0 {
+            let liquidator_rewards = self
+                .liquidator_rewards
+                .get(liquidator_id)
+                .unwrap_or(0)
+                .checked_add(amounts.liquidator)
+                .unwrap_or_else(|| env::panic_str("Liquidator rewards overflow"));
+            self.liquidator_rewards
+                .insert(liquidator_id, &liquidator_rewards);
+        }
+
+        self.protocol_rewards = self
+            .protocol_rewards
+            .checked_add(amounts.protocol)
+            .unwrap_or_else(|| env::panic_str("Protocol rewards overflow"));
+    }
+
+    pub fn record_liquidator_rewards_withdrawal(&mut self, account_id: &AccountId, amount: u128) {
+        let liquidator_rewards = self
+            .liquidator_rewards
+            .get(account_id)
+            .unwrap_or(0)
+            .checked_sub(amount)
+            .unwrap_or_else(|| env::panic_str("Liquidator rewards withdrawal underflow"));
+
+        if liquidator_rewards == 0 {
+            self.liquidator_rewards.remove(account_id);
+        } else {
+            self.liquidator_rewards
+                .insert(account_id, &liquidator_rewards);
+        }
+
+        MarketEvent::LiquidatorRewardsWithdrawal {
+            account_id,
+            amount: amount.into(),
+            liquidator_rewards: liquidator_rewards.into(),
+        }
+        .emit();
+    }
+
+    /// Reverses [`Market::record_liquidator_rewards_withdrawal`], e.g. when
+    /// the transfer to the liquidator fails.
+    pub fn revert_liquidator_rewards_withdrawal(&mut self, account_id: &AccountId, amount: u128) {
+        let liquidator_rewards = self
+            .liquidator_rewards
+            .get(account_id)
+            .unwrap_or(0)
+            .checked_add(amount)
+            .unwrap_or_else(|| env::panic_str("Liquidator rewards overflow"));
+        self.liquidator_rewards
+            .insert(account_id, &liquidator_rewards);
+
+        MarketEvent::LiquidatorRewardsWithdrawalReverted {
+            account_id,
+            amount: amount.into(),
+            liquidator_rewards: liquidator_rewards.into(),
+        }
+        .emit();
+    }
+
+    pub fn record_protocol_rewards_withdrawal(&mut self, amount: u128) {
+        self.protocol_rewards = self
+            .protocol_rewards
+            .checked_sub(amount)
+            .unwrap_or_else(|| env::panic_str("Protocol rewards withdrawal underflow"));
+
+        MarketEvent::ProtocolRewardsWithdrawal {
+            amount: amount.into(),
+            protocol_rewards: self.protocol_rewards.into(),
+        }
+        .emit();
+    }
+
+    /// Reverses [`Market::record_protocol_rewards_withdrawal`], e.g. when the
+    /// transfer to the protocol account fails.
+    pub fn revert_protocol_rewards_withdrawal(&mut self, amount: u128) {
+        self.protocol_rewards = self
+            .protocol_rewards
+            .checked_add(amount)
+            .unwrap_or_else(|| env::panic_str("Protocol rewards overflow"));
+
+        MarketEvent::ProtocolRewardsWithdrawalReverted {
+            amount: amount.into(),
+            protocol_rewards: self.protocol_rewards.into(),
+        }
+        .emit();
+    }
+}
+
+fn div_ceil(numerator: U256, denominator: U256) -> U256 {
+    let (quotient, remainder) = numerator.div_mod(denominator);
+    if remainder.is_zero() {
+        quotient
+    } else {
+        quotient + 1
+    }
+}
+
+/// `value * (numerator + 1) / (denominator + 1)`. The offset keeps the share
+/// price defined while there are no shares, and makes it expensive to
+/// manipulate the price of the first shares with donations.
+fn convert(value: u128, numerator: u128, denominator: u128, round_up: bool) -> u128 {
+    let product = U256::from(value) * (U256::from(numerator) + 1);
+    let denominator = U256::from(denominator) + 1;
+    let mut result = product / denominator;
+
+    if round_up && !(product % denominator).is_zero() {
+        result += U256::one();
+    }
+
+    u128::try_from(result).unwrap_or_else(|_| env::panic_str("Supply share conversion overflow"))
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::{test_utils::VMContextBuilder, testing_env};
+
+    use crate::{
+        fee::{Fee, MS_PER_YEAR},
+        interest_rate_model::InterestRateModel,
+        market::configuration::tests::sample_configuration,
+        rational::Rational,
+    };
+
+    use super::*;
+
+    fn market() -> Market {
+        Market::new(b"m", sample_configuration())
+    }
+
+    fn assets(market: &Market, account_id: &AccountId) -> u128 {
+        market.convert_to_assets(market.get_supply_position(account_id).unwrap().shares.0)
+    }
+
+    #[test]
+    fn rewards_raise_share_price() {
+        let alice: AccountId = "alice.near".parse().unwrap();
+        let bob: AccountId = "bob.near".parse().unwrap();
+        let mut market = market();
+
+        market.record_supply_position_borrow_asset_deposit(&alice, 300);
+        market.record_supply_position_borrow_asset_deposit(&bob, 100);
+        market.record_borrow_asset_withdrawal_fee(400);
+
+        assert_eq!(market.supply_shares, 400);
+        assert_eq!(market.borrow_asset_deposited, 800);
+        // The offset in the share price loses a little to rounding.
+        assert_eq!(assets(&market, &alice), 599);
+        assert_eq!(assets(&market, &bob), 199);
+
+        // Deposits made after a distribution mint fewer shares.
+        market.record_supply_position_borrow_asset_deposit(&bob, 100);
+        assert_eq!(market.get_supply_position(&bob).unwrap().shares.0, 150);
+        assert_eq!(assets(&market, &bob), 299);
+
+        market.record_borrow_asset_withdrawal_fee(450);
+        assert_eq!(assets(&market, &alice), 898);
+        assert_eq!(assets(&market, &bob), 449);
+    }
+
+    #[test]
+    #[should_panic = "Supply amount is too small to mint any shares"]
+    fn deposits_must_mint_shares() {
+        let alice: AccountId = "alice.near".parse().unwrap();
+        let bob: AccountId = "bob.near".parse().unwrap();
+        let mut market = market();
+
+        market.record_supply_position_borrow_asset_deposit(&alice, 1);
+        market.record_borrow_asset_withdrawal_fee(1000);
+        market.record_supply_position_borrow_asset_deposit(&bob, 1);
+    }
+
+    #[test]
+    fn withdrawals_burn_shares_rounding_up() {
+        let alice: AccountId = "alice.near".parse().unwrap();
+        let bob: AccountId = "bob.near".parse().unwrap();
+        let mut market = market();
+
+        market.record_supply_position_borrow_asset_deposit(&alice, 100);
+        market.record_supply_position_borrow_asset_deposit(&bob, 200);
+        market.record_borrow_asset_withdrawal_fee(300);
+
+        // Each share is worth a little less than 2.
+        market.record_supply_position_borrow_asset_withdrawal(&alice, 1);
+        assert_eq!(market.get_supply_position(&alice).unwrap().shares.0, 99);
+
+        let amount = assets(&market, &alice);
+        market.record_supply_position_borrow_asset_withdrawal(&alice, amount);
+        assert_eq!(market.get_supply_position(&alice).unwrap().shares.0, 0);
+        assert!(assets(&market, &bob) >= 399);
+    }
+
+    #[test]
+    fn reverted_withdrawals_restore_shares() {
+        let alice: AccountId = "alice.near".parse().unwrap();
+        let bob: AccountId = "bob.near".parse().unwrap();
+        let mut market = market();
+
+        market.record_supply_position_borrow_asset_deposit(&alice, 100);
+        market.record_supply_position_borrow_asset_deposit(&bob, 200);
+        market.record_borrow_asset_withdrawal_fee(300);
+        let started_at = market
+            .get_supply_position(&alice)
+            .unwrap()
+            .started_at_block_timestamp_ms;
+
+        let amount = assets(&market, &alice);
+        let shares = market.record_supply_position_borrow_asset_withdrawal(&alice, amount);
+        assert_eq!(shares, 100);
+        assert!(market
+            .get_supply_position(&alice)
+            .unwrap()
+            .started_at_block_timestamp_ms
+            .is_none());
+
+        market.revert_supply_position_borrow_asset_withdrawal(
+            &alice,
+            amount,
+            shares,
+            started_at.unwrap().0,
+        );
+
+        let alice_position = market.get_supply_position(&alice).unwrap();
+        assert_eq!(alice_position.shares.0, 100);
+        assert_eq!(alice_position.started_at_block_timestamp_ms, started_at);
+        assert_eq!(assets(&market, &alice), amount);
+        assert_eq!(market.supply_shares, 300);
+        assert_eq!(market.borrow_asset_deposited, 600);
+        assert_eq!(market.borrow_asset_balance, 600);
+    }
+
+    #[test]
+    fn rewards_without_shares_are_carried_over() {
+        let alice: AccountId = "alice.near".parse().unwrap();
+        let mut market = market();
+
+        market.record_borrow_asset_withdrawal_fee(100);
+        market.record_supply_position_borrow_asset_deposit(&alice, 1000);
+        assert_eq!(assets(&market, &alice), 1000);
+
+        market.record_borrow_asset_withdrawal_fee(10);
+        assert_eq!(market.borrow_asset_deposited, 1110);
+        assert_eq!(assets(&market, &alice), 1109);
+    }
+
+    #[test]
+    fn rewards_with_large_amounts() {
+        let alice: AccountId = "alice.near".parse().unwrap();
+        let bob: AccountId = "bob.near".parse().unwrap();
+        let mut market = market();
+
+        market.record_supply_position_borrow_asset_deposit(&alice, u128::MAX / 4);
+        market.record_supply_position_borrow_asset_deposit(&bob, 1);
+        market.record_borrow_asset_withdrawal_fee(u128::MAX / 2);
+
+        let alice_assets = assets(&market, &alice);
+        let bob_assets = assets(&market, &bob);
+        assert!(alice_assets + bob_assets <= market.borrow_asset_deposited);
+        assert!(alice_assets >= u128::MAX / 4 + u128::MAX / 2 - 8);
+    }
+
+    #[test]
+    fn shares_are_transferable() {
+        let alice: AccountId = "alice.near".parse().unwrap();
+        let bob: AccountId = "bob.near".parse().unwrap();
+        let mut market = market();
+
+        market.record_supply_position_borrow_asset_deposit(&alice, 100);
+        market.record_supply_shares_transfer(&alice, &bob, 40, None);
+
+        assert_eq!(market.get_supply_position(&alice).unwrap().shares.0, 60);
+        assert_eq!(market.get_supply_position(&bob).unwrap().shares.0, 40);
+        assert_eq!(market.supply_shares, 100);
+
+        market.record_supply_shares_transfer(&alice, &bob, 60, None);
+        let alice_position = market.get_supply_position(&alice).unwrap();
+        assert!(!alice_position.exists());
+        assert!(alice_position.started_at_block_timestamp_ms.is_none());
+        assert_eq!(assets(&market, &bob), 100);
+    }
+
+    #[test]
+    fn liquidation_margin_is_split() {
+        let supplier: AccountId = "supplier.near".parse().unwrap();
+        let borrower: AccountId = "borrower.near".parse().unwrap();
+        let liquidator: AccountId = "liquidator.near".parse().unwrap();
+        let mut market = market();
+
+        market.record_supply_position_borrow_asset_deposit(&supplier, 1000);
+        market.record_borrow_position_collateral_asset_deposit(&borrower, 500);
+        market.record_borrow_position_borrow_asset_withdrawal(&borrower, 100, 100);
+
+        let margin = market.record_full_liquidation(&borrower, &liquidator, 300);
+        assert_eq!(margin, 200);
+        assert_eq!(market.borrow_asset_balance, 1000);
+        market.record_liquidation_margin(&liquidator, margin);
+
+        let borrow_position = market.get_borrow_position(&borrower).unwrap();
+        assert_eq!(borrow_position.borrow_asset_liability.0, 0);
+        assert_eq!(borrow_position.collateral_asset_deposit.0, 0);
+
+        assert_eq!(market.borrow_asset_deposited, 1150);
+        assert_eq!(assets(&market, &supplier), 1149);
+        assert_eq!(market.liquidator_rewards.get(&liquidator), Some(25));
+        assert_eq!(market.protocol_rewards, 25);
+        // The liquidator's and the protocol's shares are not lendable.
+        assert_eq!(market.borrow_asset_balance, 1150);
+        assert_eq!(market.get_borrow_asset_metrics().used.0, 0);
+
+        market.record_liquidator_rewards_withdrawal(&liquidator, 25);
+        market.record_protocol_rewards_withdrawal(5);
+        assert_eq!(market.liquidator_rewards.get(&liquidator), None);
+        assert_eq!(market.protocol_rewards, 20);
+        assert_eq!(market.borrow_asset_balance, 1150);
+    }
+
+    #[test]
+    fn liquidation_rewards_are_not_lendable() {
+        let supplier: AccountId = "supplier.near".parse().unwrap();
+        let borrower: AccountId = "borrower.near".parse().unwrap();
+        let liquidator: AccountId = "liquidator.near".parse().unwrap();
+        let mut market = market();
+
+        market.record_supply_position_borrow_asset_deposit(&supplier, 1000);
+        market.record_borrow_position_collateral_asset_deposit(&borrower, 500);
+        market.record_borrow_position_borrow_asset_withdrawal(&borrower, 100, 100);
+        let margin = market.record_full_liquidation(&borrower, &liquidator, 300);
+        market.record_liquidation_margin(&liquidator, margin);
+
+        // The supplier withdraws everything their shares are worth, which
+        // leaves only rounding dust for suppliers.
+        let supplier_assets = assets(&market, &supplier);
+        market.record_supply_position_borrow_asset_withdrawal(&supplier, supplier_assets);
+        assert_eq!(market.borrow_asset_balance, 1);
+        assert_eq!(market.borrow_asset_deposited, 1);
+
+        // The liquidator's and the protocol's shares are still withdrawable.
+        market.record_liquidator_rewards_withdrawal(&liquidator, 25);
+        market.record_protocol_rewards_withdrawal(25);
+        assert_eq!(market.liquidator_rewards.get(&liquidator), None);
+        assert_eq!(market.protocol_rewards, 0);
+        assert_eq!(market.borrow_asset_balance, 1);
+    }
+
+    #[test]
+    fn reverted_liquidations_keep_the_borrow_start() {
+        let supplier: AccountId = "supplier.near".parse().unwrap();
+        let borrower: AccountId = "borrower.near".parse().unwrap();
+        let liquidator: AccountId = "liquidator.near".parse().unwrap();
+        let mut market = market();
+
+        set_block_timestamp_ms(1_000);
+        market.record_supply_position_borrow_asset_deposit(&supplier, 1000);
+        market.record_borrow_position_collateral_asset_deposit(&borrower, 500);
+        market.record_borrow_position_borrow_asset_withdrawal(&borrower, 100, 100);
+        let started_at = market
+            .get_borrow_position(&borrower)
+            .unwrap()
+            .started_at_block_timestamp_ms;
+        assert_eq!(started_at, Some(1_000.into()));
+
+        set_block_timestamp_ms(5_000);
+        let liability = market
+            .get_borrow_position(&borrower)
+            .unwrap()
+            .borrow_asset_liability
+            .0;
+        market.record_full_liquidation(&borrower, &liquidator, liability);
+        market.revert_liquidation(
+            &borrower,
+            liability,
+            500,
+            started_at.map(|started_at| started_at.0),
+        );
+
+        let borrow_position = market.get_borrow_position(&borrower).unwrap();
+        assert_eq!(borrow_position.borrow_asset_liability.0, liability);
+        assert_eq!(borrow_position.collateral_asset_deposit.0, 500);
+        assert_eq!(borrow_position.started_at_block_timestamp_ms, started_at);
+    }
+
+    #[test]
+    fn uncollateralized_liability_is_written_off() {
+        let supplier: AccountId = "supplier.near".parse().unwrap();
+        let borrower: AccountId = "borrower.near".parse().unwrap();
+        let liquidator: AccountId = "liquidator.near".parse().unwrap();
+        let mut market = market();
+
+        market.record_supply_position_borrow_asset_deposit(&supplier, 1000);
+        market.record_borrow_position_collateral_asset_deposit(&borrower, 500);
+        market.record_borrow_position_borrow_asset_withdrawal(&borrower, 100, 100);
+
+        let margin = market.record_full_liquidation(&borrower, &liquidator, 60);
+        assert_eq!(margin, 0);
+        assert_eq!(market.borrow_asset_liability, 40);
+
+        assert_eq!(market.record_borrow_position_write_off(&borrower), 40);
+        let borrow_position = market.get_borrow_position(&borrower).unwrap();
+        assert_eq!(borrow_position.borrow_asset_liability.0, 0);
+        assert_eq!(borrow_position.started_at_block_timestamp_ms, None);
+        assert_eq!(market.borrow_asset_liability, 0);
+        assert_eq!(market.borrow_asset_deposited, 960);
+        assert_eq!(market.borrow_asset_balance, 960);
+        assert_eq!(assets(&market, &supplier), 960);
+
+        // Nothing is left to write off.
+        assert_eq!(market.record_borrow_position_write_off(&borrower), 0);
+    }
+
+    fn set_block_timestamp_ms(block_timestamp_ms: u64) {
+        testing_env!(VMContextBuilder::new()
+            .block_timestamp(block_timestamp_ms * 1_000_000)
+            .build());
+    }
+
+    #[test]
+    fn interest_accrues_to_suppliers() {
+        let supplier: AccountId = "supplier.near".parse().unwrap();
+        let borrower: AccountId = "borrower.near".parse().unwrap();
+        let mut configuration = sample_configuration();
+        configuration.interest_rate_model = InterestRateModel::Fixed {
+            rate: Rational::new(10, 100),
+        };
+        configuration.annual_maintenance_fee = Fee::Proportional(Rational::new(2, 100));
+        let mut market = Market::new(b"m", configuration);
+
+        set_block_timestamp_ms(0);
+        market.record_supply_position_borrow_asset_deposit(&supplier, 10_000);
+        market.record_borrow_position_collateral_asset_deposit(&borrower, 5_000);
+        market.record_borrow_position_borrow_asset_withdrawal(&borrower, 1_000, 1_000);
+
+        // 10% interest and a 2% maintenance fee over half a year.
+        set_block_timestamp_ms(MS_PER_YEAR / 2);
+        assert_eq!(
+            market
+                .get_borrow_position(&borrower)
+                .unwrap()
+                .borrow_asset_liability
+                .0,
+            1_060,
+        );
+
+        set_block_timestamp_ms(MS_PER_YEAR);
+        assert_eq!(
+            market
+                .get_borrow_position(&borrower)
+                .unwrap()
+                .borrow_asset_liability
+                .0,
+            1_120,
+        );
+
+        assert_eq!(
+            market.record_borrow_position_borrow_asset_repay(&borrower, 2_000),
+            1_120,
+        );
+        assert_eq!(market.borrow_asset_deposited, 10_120);
+        assert_eq!(market.borrow_asset_balance, 10_120);
+        // The offset in the share price loses a little to rounding.
+        assert_eq!(assets(&market, &supplier), 10_119);
+    }
+
+    #[test]
+    fn interest_accrues_at_the_utilization_of_each_period() {
+        let supplier: AccountId = "supplier.near".parse().unwrap();
+        let borrower: AccountId = "borrower.near".parse().unwrap();
+        let mut configuration = sample_configuration();
+        // The borrow rate is 20% of the utilization.
+        configuration.interest_rate_model = InterestRateModel::JumpRate {
+            base_rate: Rational::new(0, 1),
+            slope1: Rational::new(20, 100),
+            optimal_utilization: Rational::new(1, 1),
+            slope2: Rational::new(0, 1),
+        };
+        let mut market = Market::new(b"m", configuration);
+
+        set_block_timestamp_ms(0);
+        market.record_supply_position_borrow_asset_deposit(&supplier, 1_000);
+        market.record_borrow_position_collateral_asset_deposit(&borrower, 5_000);
+        market.record_borrow_position_borrow_asset_withdrawal(&borrower, 500, 500);
+
+        // 50% utilization, 10% for half a year. The interest is distributed
+        // before the deposit changes the utilization.
+        set_block_timestamp_ms(MS_PER_YEAR / 2);
+        market.record_supply_position_borrow_asset_deposit(&supplier, 1_475);
+        assert_eq!(market.borrow_asset_deposited, 2_500);
+        assert_eq!(market.borrow_asset_liability, 525);
+
+        // 21% utilization, 4.2% for the other half.
+        set_block_timestamp_ms(MS_PER_YEAR);
+        assert_eq!(
+            market
+                .get_borrow_position(&borrower)
+                .unwrap()
+                .borrow_asset_liability
+                .0,
+            537,
+        );
+    }
+
+    #[test]
+    fn repay_is_capped_at_liability() {
+        let supplier: AccountId = "supplier.near".parse().unwrap();
+        let borrower: AccountId = "borrower.near".parse().unwrap();
+        let mut market = market();
+
+        market.record_supply_position_borrow_asset_deposit(&supplier, 1000);
+        market.record_borrow_position_collateral_asset_deposit(&borrower, 500);
+        market.record_borrow_position_borrow_asset_withdrawal(&borrower, 100, 100);
+
+        assert_eq!(
+            market.record_borrow_position_borrow_asset_repay(&borrower, 60),
+            60
+        );
+        assert_eq!(
+            market.record_borrow_position_borrow_asset_repay(&borrower, 60),
+            40
+        );
+        assert_eq!(
+            market
+                .get_borrow_position(&borrower)
+                .unwrap()
+                .borrow_asset_liability
+                .0,
+            0,
+        );
+        assert_eq!(market.borrow_asset_balance, 1000);
+
+        let stranger: AccountId = "stranger.near".parse().unwrap();
+        assert_eq!(
+            market.record_borrow_position_borrow_asset_repay(&stranger, 60),
+            0
+        );
+        assert!(market.get_borrow_position(&stranger).is_none());
+    }
+
+    #[test]
+    fn origination_fee_is_owed_to_suppliers() {
+        let supplier: AccountId = "supplier.near".parse().unwrap();
+        let borrower: AccountId = "borrower.near".parse().unwrap();
+        let mut market = market();
+
+        market.record_supply_position_borrow_asset_deposit(&supplier, 1000);
+        market.record_borrow_position_collateral_asset_deposit(&borrower, 500);
+        market.record_borrow_position_borrow_asset_withdrawal(&borrower, 110, 100);
+        assert_eq!(market.borrow_asset_deposited, 1010);
+        assert_eq!(market.borrow_asset_balance, 900);
+        assert_eq!(market.borrow_asset_used(), market.borrow_asset_liability);
+
+        market.revert_borrow_position_borrow_asset_withdrawal(&borrower, 110, 100);
+        assert_eq!(market.borrow_asset_deposited, 1000);
+        assert_eq!(market.borrow_asset_balance, 1000);
+        assert_eq!(market.borrow_asset_used(), 0);
+
+        market.record_borrow_position_borrow_asset_withdrawal(&borrower, 110, 100);
+        market.record_borrow_position_borrow_asset_repay(&borrower, 110);
+        assert_eq!(market.borrow_asset_deposited, 1010);
+        assert_eq!(market.borrow_asset_balance, 1010);
+        assert_eq!(market.get_borrow_asset_metrics().used.0, 0);
+    }
+
+    #[test]
+    fn empty_positions_are_removed() {
+        let alice: AccountId = "alice.near".parse().unwrap();
+        let mut market = market();
+
+        market.record_supply_position_borrow_asset_deposit(&alice, 100);
+        market.record_borrow_position_collateral_asset_deposit(&alice, 50);
+        assert!(market.has_open_positions(&alice));
+
+        market.record_supply_position_borrow_asset_withdrawal(&alice, 100);
+        assert!(market.has_open_positions(&alice));
+
+        market.withdrawal_queue.insert_or_update(&alice, 10);
+        market.record_borrow_position_collateral_asset_withdrawal(&alice, 50);
+        assert!(market.has_open_positions(&alice));
+
+        market.withdrawal_queue.remove(&alice);
+        assert!(!market.has_open_positions(&alice));
+
+        market.remove_empty_positions(&alice);
+        assert!(market.get_supply_position(&alice).is_none());
+        assert!(market.get_borrow_position(&alice).is_none());
+    }
+
+    #[test]
+    fn state_changes_emit_events() {
+        let alice: AccountId = "alice.near".parse().unwrap();
+        let mut market = market();
+
+        market.record_supply_position_borrow_asset_deposit(&alice, 300);
+        market.record_borrow_position_collateral_asset_deposit(&alice, 100);
+
+        let events = near_sdk::test_utils::get_logs()
+            .iter()
+            .map(|log| {
+                near_sdk::serde_json::from_str::<near_sdk::serde_json::Value>(
+                    log.strip_prefix("EVENT_JSON:").unwrap(),
+                )
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(events.len(), 3);
+
+        assert_eq!(events[0]["standard"], "nep141");
+        assert_eq!(events[0]["event"], "ft_mint");
+        assert_eq!(events[0]["data"][0]["owner_id"], "alice.near");
+        assert_eq!(events[0]["data"][0]["amount"], "300");
+
+        assert!(events[1..]
+            .iter()
+            .all(|event| event["standard"] == "templar-market" && event["version"] == "1.0.0"));
+
+        assert_eq!(events[1]["event"], "supply_deposit");
+        assert_eq!(events[1]["data"]["account_id"], "alice.near");
+        assert_eq!(events[1]["data"]["amount"], "300");
+        assert_eq!(events[1]["data"]["supply_position"]["shares"], "300");
+
+        assert_eq!(events[2]["event"], "collateral_deposit");
+        assert_eq!(
+            events[2]["data"]["borrow_position"]["collateral_asset_deposit"],
+            "100",
+        );
     }
 }
@@ -1,10 +1,12 @@
 use near_sdk::json_types::U128;
 use near_sdk::{near, require, AccountId};
 
-use crate::rational::Rational;
+use crate::{number::U256, rational::Rational};
 
 mod configuration;
 pub use configuration::*;
+mod event;
+pub use event::*;
 mod external;
 pub use external::*;
 mod r#impl;
@@ -12,7 +14,7 @@ pub use r#impl::*;
 
 /// Borrow asset metrics are related as follows:
 ///
-/// ```
+/// ```text
 /// available = floor(deposited * maximum_borrow_asset_usage_ratio) - used
 /// used = deposited - balance
 /// ```
@@ -28,7 +30,6 @@ pub struct BorrowAssetMetrics {
 impl BorrowAssetMetrics {
     pub fn calculate(deposited: u128, balance: u128, maximum_usage_ratio: Rational<u128>) -> Self {
         require!(deposited >= balance);
-
         let used = deposited - balance;
 
         let available = maximum_usage_ratio
@@ -112,6 +113,59 @@ fn test_available_formula() {
     }
 }
 
+/// Annual rates from the market's interest rate model at the current
+/// utilization. The borrow rate does not include the maintenance fee.
+#[derive(Clone, Debug)]
+#[near(serializers = [json])]
+pub struct InterestRates {
+    pub borrow_apr: Rational<u128>,
+    pub supply_apy: Rational<u128>,
+}
+
+/// Who may liquidate unhealthy borrow positions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[near(serializers = [json, borsh])]
+pub enum LiquidationPolicy {
+    /// Only this account.
+    Single(AccountId),
+    /// Only these accounts.
+    Allowlist(Vec<AccountId>),
+    /// Any account.
+    Open,
+}
+
+impl LiquidationPolicy {
+    pub fn is_allowed(&self, account_id: &AccountId) -> bool {
+        match self {
+            Self::Single(liquidator_account_id) => liquidator_account_id == account_id,
+            Self::Allowlist(liquidator_account_ids) => liquidator_account_ids.contains(account_id),
+            Self::Open => true,
+        }
+    }
+}
+
+#[test]
+fn test_liquidation_policy() {
+    let alice: AccountId = "alice.near".parse().unwrap();
+    let bob: AccountId = "bob.near".parse().unwrap();
+    let carol: AccountId = "carol.near".parse().unwrap();
+
+    let policy = LiquidationPolicy::Single(alice.clone());
+    assert!(policy.is_allowed(&alice));
+    assert!(!policy.is_allowed(&bob));
+
+    let policy = LiquidationPolicy::Allowlist(vec![alice.clone(), bob.clone()]);
+    assert!(policy.is_allowed(&alice));
+    assert!(policy.is_allowed(&bob));
+    assert!(!policy.is_allowed(&carol));
+
+    let policy = LiquidationPolicy::Allowlist(vec![]);
+    assert!(!policy.is_allowed(&alice));
+
+    assert!(LiquidationPolicy::Open.is_allowed(&carol));
+}
+
+/// Relative weights by which the margin recovered in a liquidation is split.
 #[derive(Clone, Debug)]
 #[near(serializers = [json, borsh])]
 pub struct LiquidationSpread {
@@ -121,25 +175,225 @@ pub struct LiquidationSpread {
     // pub insurance: U128,
 }
 
+/// Shares of a liquidation margin, see [`LiquidationSpread::split`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LiquidationSpreadAmounts {
+    pub supply_position: u128,
+    pub liquidator: u128,
+    pub protocol: u128,
+}
+
+impl LiquidationSpread {
+    /// Splits `amount` proportionally to the weights. Rounding dust, and the
+    /// whole amount if all weights are zero, goes to supply positions.
+    pub fn split(&self, amount: u128) -> LiquidationSpreadAmounts {
+        let total = U256::from(self.supply_position.0)
+            + U256::from(self.liquidator.0)
+            + U256::from(self.protocol.0);
+
+        let share = |weight: U128| {
+            if total.is_zero() {
+                return 0;
+            }
+            // `weight <= total`, so the share fits in a `u128`.
+            (U256::from(amount) * U256::from(weight.0) / total).as_u128()
+        };
+
+        let liquidator = share(self.liquidator);
+        let protocol = share(self.protocol);
+
+        LiquidationSpreadAmounts {
+            supply_position: amount - liquidator - protocol,
+            liquidator,
+            protocol,
+        }
+    }
+}
+
+#[test]
+fn test_liquidation_spread_split() {
+    let spread = LiquidationSpread {
+        supply_position: 8.into(),
+        liquidator: 1.into(),
+        protocol: 1.into(),
+    };
+
+    assert_eq!(
+        spread.split(1000),
+        LiquidationSpreadAmounts {
+            supply_position: 800,
+            liquidator: 100,
+            protocol: 100,
+        },
+    );
+    assert_eq!(
+        spread.split(15),
+        LiquidationSpreadAmounts {
+            supply_position: 13,
+            liquidator: 1,
+            protocol: 1,
+        },
+    );
+    assert_eq!(
+        spread.split(u128::MAX),
+        LiquidationSpreadAmounts {
+            supply_position: u128::MAX - 2 * (u128::MAX / 10),
+            liquidator: u128::MAX / 10,
+            protocol: u128::MAX / 10,
+        },
+    );
+
+    let spread = LiquidationSpread {
+        supply_position: 0.into(),
+        liquidator: 0.into(),
+        protocol: 0.into(),
+    };
+    assert_eq!(spread.split(1000).supply_position, 1000);
+}
+
 #[near(serializers = [json])]
 pub enum Nep141MarketDepositMessage {
     Supply,
     Collateralize,
+    /// Any amount exceeding the liability is refunded.
     Repay,
+    /// Repays the borrow position of another account. Any amount exceeding
+    /// its liability is refunded.
+    RepayFor(RepayForMsg),
     Liquidate(LiquidateMsg),
 }
 
+impl Nep141MarketDepositMessage {
+    pub fn action(&self) -> MarketAction {
+        match self {
+            Self::Supply => MarketAction::Supply,
+            Self::Collateralize => MarketAction::Collateralize,
+            Self::Repay | Self::RepayFor(_) => MarketAction::Repay,
+            Self::Liquidate(_) => MarketAction::Liquidate,
+        }
+    }
+}
+
+#[near(serializers = [json])]
+pub struct RepayForMsg {
+    pub account_id: AccountId,
+}
+
 #[near(serializers = [json])]
 pub struct LiquidateMsg {
     pub account_id: AccountId,
-    pub oracle_price_proof: OraclePriceProof,
 }
 
-/// This represents some sort of proof-of-price from a price oracle, e.g. Pyth.
-/// In production, it must be validated, but for now it's just trust me bro.
+/// Prices of the smallest units of the market's assets, in a common quote
+/// currency. Created from the configured price oracle by
+/// [`crate::oracle::PriceOracleConfiguration::create_price_proof`].
 #[derive(Clone, Copy, Debug)]
 #[near(serializers = [json])]
 pub struct OraclePriceProof {
     pub collateral_asset_price: Rational<u128>,
     pub borrow_asset_price: Rational<u128>,
 }
+
+/// Market actions that can be paused independently.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[near(serializers = [json, borsh])]
+pub enum MarketAction {
+    Supply,
+    Collateralize,
+    Borrow,
+    Repay,
+    Liquidate,
+    /// Processing of the supply withdrawal queue.
+    Withdraw,
+    WithdrawCollateral,
+    /// Withdrawal of liquidator and protocol rewards.
+    WithdrawRewards,
+    /// NEP-141 transfers of supply shares.
+    TransferShares,
+}
+
+impl MarketAction {
+    /// Paused when no actions are specified. Repayments and liquidations
+    /// remain available so that positions can still be made healthy.
+    pub const DEFAULT_PAUSE: [Self; 7] = [
+        Self::Supply,
+        Self::Collateralize,
+        Self::Borrow,
+        Self::Withdraw,
+        Self::WithdrawCollateral,
+        Self::WithdrawRewards,
+        Self::TransferShares,
+    ];
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[near(serializers = [json, borsh])]
+pub struct PausedActions {
+    pub supply: bool,
+    pub collateralize: bool,
+    pub borrow: bool,
+    pub repay: bool,
+    pub liquidate: bool,
+    pub withdraw: bool,
+    pub withdraw_collateral: bool,
+    pub withdraw_rewards: bool,
+    pub transfer_shares: bool,
+}
+
+impl PausedActions {
+    fn flag_mut(&mut self, action: MarketAction) -> &mut bool {
+        match action {
+            MarketAction::Supply => &mut self.supply,
+            MarketAction::Collateralize => &mut self.collateralize,
+            MarketAction::Borrow => &mut self.borrow,
+            MarketAction::Repay => &mut self.repay,
+            MarketAction::Liquidate => &mut self.liquidate,
+            MarketAction::Withdraw => &mut self.withdraw,
+            MarketAction::WithdrawCollateral => &mut self.withdraw_collateral,
+            MarketAction::WithdrawRewards => &mut self.withdraw_rewards,
+            MarketAction::TransferShares => &mut self.transfer_shares,
+        }
+    }
+
+    pub fn is_paused(&self, action: MarketAction) -> bool {
+        match action {
+            MarketAction::Supply => self.supply,
+            MarketAction::Collateralize => self.collateralize,
+            MarketAction::Borrow => self.borrow,
+            MarketAction::Repay => self.repay,
+            MarketAction::Liquidate => self.liquidate,
+            MarketAction::Withdraw => self.withdraw,
+            MarketAction::WithdrawCollateral => self.withdraw_collateral,
+            MarketAction::WithdrawRewards => self.withdraw_rewards,
+            MarketAction::TransferShares => self.transfer_shares,
+        }
+    }
+
+    pub fn set_paused(&mut self, action: MarketAction, paused: bool) {
+        *self.flag_mut(action) = paused;
+    }
+}
+
+#[test]
+fn test_paused_actions() {
+    let mut paused = PausedActions::default();
+
+    for action in MarketAction::DEFAULT_PAUSE {
+        paused.set_paused(action, true);
+    }
+
+    assert!(paused.is_paused(MarketAction::Supply));
+    assert!(paused.is_paused(MarketAction::Collateralize));
+    assert!(paused.is_paused(MarketAction::Borrow));
+    assert!(paused.is_paused(MarketAction::Withdraw));
+    assert!(paused.is_paused(MarketAction::WithdrawCollateral));
+    assert!(paused.is_paused(MarketAction::WithdrawRewards));
+    assert!(paused.is_paused(MarketAction::TransferShares));
+    assert!(!paused.is_paused(MarketAction::Repay));
+    assert!(!paused.is_paused(MarketAction::Liquidate));
+
+    paused.set_paused(MarketAction::Liquidate, true);
+    paused.set_paused(MarketAction::Borrow, false);
+    assert!(paused.is_paused(MarketAction::Liquidate));
+    assert!(!paused.is_paused(MarketAction::Borrow));
+}
@@ -0,0 +1,13 @@
+// Triggered by code generated by `construct_uint!`.
+#![allow(clippy::manual_div_ceil)]
+
+uint::construct_uint! {
+    /// 256-bit unsigned integer, for intermediate values that may not fit in
+    /// a `u128`.
+    pub struct U256(4);
+}
+
+uint::construct_uint! {
+    /// 512-bit unsigned integer, for products of several `u128` values.
+    pub struct U512(8);
+}
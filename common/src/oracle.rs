@@ -0,0 +1,255 @@
+use std::{collections::HashMap, fmt::Display};
+
+use near_sdk::{
+    ext_contract,
+    json_types::{I64, U64},
+    near, AccountId, Promise,
+};
+
+use crate::{market::OraclePriceProof, rational::Rational};
+
+/// Hex-encoded identifier of a price feed, as used by Pyth.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[near(serializers = [json, borsh])]
+pub struct PriceIdentifier(pub String);
+
+/// A Pyth-style price of one whole unit of an asset: `price * 10^expo`,
+/// with a confidence interval of `conf * 10^expo`.
+#[derive(Clone, Copy, Debug)]
+#[near(serializers = [json, borsh])]
+pub struct Price {
+    pub price: I64,
+    pub conf: U64,
+    pub expo: i32,
+    /// Unix timestamp, in seconds.
+    pub publish_time: i64,
+}
+
+#[ext_contract(ext_price_oracle)]
+pub trait PriceOracle {
+    fn list_prices(
+        &self,
+        price_ids: Vec<PriceIdentifier>,
+    ) -> HashMap<PriceIdentifier, Option<Price>>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PriceError {
+    Missing,
+    NotPositive,
+    Stale,
+    Uncertain,
+    Overflow,
+}
+
+impl Display for PriceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Missing => "Price is missing",
+            Self::NotPositive => "Price is not positive",
+            Self::Stale => "Price is stale",
+            Self::Uncertain => "Price confidence interval is too wide",
+            Self::Overflow => "Price is out of range",
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+#[near(serializers = [json, borsh])]
+pub struct PriceOracleConfiguration {
+    pub account_id: AccountId,
+    pub collateral_asset_price_id: PriceIdentifier,
+    pub collateral_asset_decimals: u8,
+    pub borrow_asset_price_id: PriceIdentifier,
+    pub borrow_asset_decimals: u8,
+    /// Prices published longer ago than this (in seconds) are rejected.
+    pub price_maximum_age_s: u32,
+    /// Prices with a wider confidence interval, relative to the price, are
+    /// rejected.
+    pub maximum_confidence_ratio: Rational<u16>,
+}
+
+impl PriceOracleConfiguration {
+    /// Requests the prices of both assets. Pass the result to
+    /// [`PriceOracleConfiguration::create_price_proof`].
+    pub fn request_prices(&self) -> Promise {
+        ext_price_oracle::ext(self.account_id.clone()).list_prices(vec![
+            self.collateral_asset_price_id.clone(),
+            self.borrow_asset_price_id.clone(),
+        ])
+    }
+
+    /// Price of the smallest unit of an asset with `decimals` decimals.
+    pub fn validate_price(
+        &self,
+        price: &Price,
+        decimals: u8,
+        block_timestamp_ms: u64,
+    ) -> Result<Rational<u128>, PriceError> {
+        let value = u128::try_from(price.price.0)
+            .ok()
+            .filter(|value| *value > 0)
+            .ok_or(PriceError::NotPositive)?;
+
+        let block_timestamp_s = i64::try_from(block_timestamp_ms / 1000).unwrap_or(i64::MAX);
+        if price
+            .publish_time
+            .saturating_add(i64::from(self.price_maximum_age_s))
+            < block_timestamp_s
+        {
+            return Err(PriceError::Stale);
+        }
+
+        // conf / price <= numerator / denominator
+        if u128::from(price.conf.0) * u128::from(self.maximum_confidence_ratio.denominator())
+            > value * u128::from(self.maximum_confidence_ratio.numerator())
+        {
+            return Err(PriceError::Uncertain);
+        }
+
+        let exponent = i64::from(price.expo) - i64::from(decimals);
+        let scale = u32::try_from(exponent.unsigned_abs())
+            .ok()
+            .and_then(|exponent| 10u128.checked_pow(exponent))
+            .ok_or(PriceError::Overflow)?;
+
+        if exponent >= 0 {
+            let value = value.checked_mul(scale).ok_or(PriceError::Overflow)?;
+            Ok(Rational::new(value, 1))
+        } else {
+            Ok(Rational::new(value, scale))
+        }
+    }
+
+    pub fn create_price_proof(
+        &self,
+        prices: &HashMap<PriceIdentifier, Option<Price>>,
+        block_timestamp_ms: u64,
+    ) -> Result<OraclePriceProof, PriceError> {
+        let get = |price_id: &PriceIdentifier| {
+            prices
+                .get(price_id)
+                .copied()
+                .flatten()
+                .ok_or(PriceError::Missing)
+        };
+
+        Ok(OraclePriceProof {
+            collateral_asset_price: self.validate_price(
+                &get(&self.collateral_asset_price_id)?,
+                self.collateral_asset_decimals,
+                block_timestamp_ms,
+            )?,
+            borrow_asset_price: self.validate_price(
+                &get(&self.borrow_asset_price_id)?,
+                self.borrow_asset_decimals,
+                block_timestamp_ms,
+            )?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn configuration() -> PriceOracleConfiguration {
+        PriceOracleConfiguration {
+            account_id: "pyth-oracle.near".parse().unwrap(),
+            collateral_asset_price_id: PriceIdentifier("c0".repeat(32)),
+            collateral_asset_decimals: 24,
+            borrow_asset_price_id: PriceIdentifier("b0".repeat(32)),
+            borrow_asset_decimals: 6,
+            price_maximum_age_s: 60,
+            maximum_confidence_ratio: Rational::new(1, 100),
+        }
+    }
+
+    fn price(price: i64, conf: u64, expo: i32, publish_time: i64) -> Price {
+        Price {
+            price: price.into(),
+            conf: conf.into(),
+            expo,
+            publish_time,
+        }
+    }
+
+    #[test]
+    fn validate_price() {
+        let configuration = configuration();
+        let now_ms = 1_000_000;
+
+        // $3.50 per whole token with 24 decimals.
+        assert_eq!(
+            configuration.validate_price(&price(350_000_000, 0, -8, 1000), 24, now_ms),
+            Ok(Rational::new(35, 10u128.pow(25))),
+        );
+        assert_eq!(
+            configuration.validate_price(&price(2, 0, 3, 1000), 0, now_ms),
+            Ok(Rational::new(2000, 1)),
+        );
+
+        assert_eq!(
+            configuration.validate_price(&price(0, 0, -8, 1000), 24, now_ms),
+            Err(PriceError::NotPositive),
+        );
+        assert_eq!(
+            configuration.validate_price(&price(-1, 0, -8, 1000), 24, now_ms),
+            Err(PriceError::NotPositive),
+        );
+        assert_eq!(
+            configuration.validate_price(&price(1, 0, -8, 940), 24, now_ms),
+            Ok(Rational::new(1, 10u128.pow(32))),
+        );
+        assert_eq!(
+            configuration.validate_price(&price(1, 0, -8, 939), 24, now_ms),
+            Err(PriceError::Stale),
+        );
+        assert_eq!(
+            configuration.validate_price(&price(1000, 10, -8, 1000), 24, now_ms),
+            Ok(Rational::new(1, 10u128.pow(29))),
+        );
+        assert_eq!(
+            configuration.validate_price(&price(1000, 11, -8, 1000), 24, now_ms),
+            Err(PriceError::Uncertain),
+        );
+        assert_eq!(
+            configuration.validate_price(&price(1, 0, -20, 1000), 24, now_ms),
+            Err(PriceError::Overflow),
+        );
+    }
+
+    #[test]
+    fn create_price_proof() {
+        let configuration = configuration();
+        let mut prices = HashMap::new();
+        prices.insert(
+            configuration.collateral_asset_price_id.clone(),
+            Some(price(350_000_000, 0, -8, 1000)),
+        );
+
+        assert_eq!(
+            configuration.create_price_proof(&prices, 1_000_000).err(),
+            Some(PriceError::Missing),
+        );
+
+        prices.insert(configuration.borrow_asset_price_id.clone(), None);
+        assert_eq!(
+            configuration.create_price_proof(&prices, 1_000_000).err(),
+            Some(PriceError::Missing),
+        );
+
+        prices.insert(
+            configuration.borrow_asset_price_id.clone(),
+            Some(price(100_000_000, 0, -8, 1000)),
+        );
+        let proof = configuration
+            .create_price_proof(&prices, 1_000_000)
+            .unwrap();
+        assert_eq!(
+            proof.collateral_asset_price,
+            Rational::new(35, 10u128.pow(25))
+        );
+        assert_eq!(proof.borrow_asset_price, Rational::new(1, 10u128.pow(6)));
+    }
+}
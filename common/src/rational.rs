@@ -1,7 +1,4 @@
-use std::{
-    cmp::Ordering,
-    ops::{BitXor, Div, Sub},
-};
+use std::ops::{BitXor, Div, Rem};
 
 use near_sdk::near;
 
@@ -16,17 +13,14 @@ fn is_zero<T: BitXor<Output = T> + Copy + Eq>(x: T) -> bool {
     x ^ x == x
 }
 
-fn gcd_euclid<T: Ord + Sub<Output = T> + Copy>(mut a: T, mut b: T) -> T {
-    loop {
-        match a.cmp(&b) {
-            Ordering::Equal => return a,
-            Ordering::Less => b = b - a,
-            Ordering::Greater => a = a - b,
-        }
+fn gcd_euclid<T: Rem<Output = T> + BitXor<Output = T> + Copy + Eq>(mut a: T, mut b: T) -> T {
+    while !is_zero(b) {
+        (a, b) = (b, a % b);
     }
+    a
 }
 
-impl<T: Div<Output = T> + BitXor<Output = T> + Sub<Output = T> + Copy + Eq + Ord> Rational<T> {
+impl<T: Div<Output = T> + BitXor<Output = T> + Rem<Output = T> + Copy + Eq + Ord> Rational<T> {
     pub fn new(a: T, b: T) -> Self {
         Self(a, b).simplify()
     }
@@ -34,7 +28,7 @@ impl<T: Div<Output = T> + BitXor<Output = T> + Sub<Output = T> + Copy + Eq + Ord
     pub fn simplify(self) -> Self {
         let Self(mut n, mut d) = self;
 
-        if !is_zero(n) && !is_zero(d) {
+        if !is_zero(d) {
             let gcd = gcd_euclid(n, d);
             n = n / gcd;
             d = d / gcd;
@@ -140,4 +134,6 @@ fn test_gcd() {
     assert_eq!(gcd_euclid(5, 15), 5);
     assert_eq!(gcd_euclid(27, 6), 3);
     assert_eq!(gcd_euclid(200, 17), 1);
+    assert_eq!(gcd_euclid(1, 1_000_000_000_000_000_000u128), 1);
+    assert_eq!(gcd_euclid(7, 0), 7);
 }
@@ -3,36 +3,71 @@ use near_sdk::{
     near,
 };
 
+use crate::number::U256;
+
 #[near(serializers = [json, borsh])]
 pub struct SupplyPosition {
-    pub borrow_asset_deposited: U128,
-    pub borrow_asset_rewards: RewardRecord,
+    /// Average time at which the current shares were acquired, weighted by
+    /// the number of shares. Reset once all of them are withdrawn or
+    /// transferred.
+    pub started_at_block_timestamp_ms: Option<U64>,
+    /// Shares of the market's borrow asset deposits, including rewards. They
+    /// are also the account's balance of the market's NEP-141 token.
+    pub shares: U128,
     pub collateral_asset_rewards: RewardRecord,
 }
 
 impl SupplyPosition {
     pub fn new(block_height: u64) -> Self {
         Self {
-            borrow_asset_deposited: 0.into(),
-            borrow_asset_rewards: RewardRecord::new(block_height),
+            started_at_block_timestamp_ms: None,
+            shares: 0.into(),
             collateral_asset_rewards: RewardRecord::new(block_height),
         }
     }
 
     pub fn exists(&self) -> bool {
-        self.borrow_asset_deposited.0 != 0
-            || self.borrow_asset_rewards.amount.0 != 0
-            || self.collateral_asset_rewards.amount.0 != 0
+        self.shares.0 != 0 || self.collateral_asset_rewards.amount.0 != 0
     }
 
-    pub fn deposit_borrow_asset(&mut self, amount: u128) -> Option<U128> {
-        self.borrow_asset_deposited.0 = self.borrow_asset_deposited.0.checked_add(amount)?;
-        Some(self.borrow_asset_deposited)
+    /// Moves the start of the position to the average of its current start
+    /// and `acquired_at_block_timestamp_ms`, weighted by the number of shares,
+    /// so that new shares do not inherit the age of the existing ones.
+    pub fn deposit_shares(
+        &mut self,
+        shares: u128,
+        acquired_at_block_timestamp_ms: u64,
+    ) -> Option<U128> {
+        let total = self.shares.0.checked_add(shares)?;
+
+        let started_at = match self.started_at_block_timestamp_ms {
+            Some(started_at) if total > 0 => {
+                let weighted = U256::from(started_at.0) * U256::from(self.shares.0)
+                    + U256::from(acquired_at_block_timestamp_ms) * U256::from(shares);
+                // An average of two `u64` values fits in a `u64`.
+                (weighted / U256::from(total)).as_u64()
+            }
+            _ => acquired_at_block_timestamp_ms,
+        };
+
+        self.shares.0 = total;
+        self.started_at_block_timestamp_ms = Some(started_at.into());
+        Some(self.shares)
     }
 
-    pub fn withdraw_borrow_asset(&mut self, amount: u128) -> Option<U128> {
-        self.borrow_asset_deposited.0 = self.borrow_asset_deposited.0.checked_sub(amount)?;
-        Some(self.borrow_asset_deposited)
+    pub fn withdraw_shares(&mut self, shares: u128) -> Option<U128> {
+        self.shares.0 = self.shares.0.checked_sub(shares)?;
+        if self.shares.0 == 0 {
+            self.started_at_block_timestamp_ms = None;
+        }
+        Some(self.shares)
+    }
+
+    /// How long the current shares have been held, in milliseconds.
+    pub fn deposit_duration_ms(&self, block_timestamp_ms: u64) -> u64 {
+        self.started_at_block_timestamp_ms.map_or(0, |started_at| {
+            block_timestamp_ms.saturating_sub(started_at.0)
+        })
     }
 }
 
@@ -61,3 +96,34 @@ impl RewardRecord {
         self.last_updated_block_height.0 = block_height;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deposits_move_the_start_to_the_weighted_average() {
+        let mut supply_position = SupplyPosition::new(0);
+
+        supply_position.deposit_shares(300, 1000).unwrap();
+        assert_eq!(supply_position.deposit_duration_ms(5000), 4000);
+
+        // A quarter of the shares is new.
+        supply_position.deposit_shares(100, 5000).unwrap();
+        assert_eq!(
+            supply_position.started_at_block_timestamp_ms,
+            Some(2000.into())
+        );
+
+        supply_position
+            .deposit_shares(u128::MAX - 400, 2000)
+            .unwrap();
+        assert_eq!(
+            supply_position.started_at_block_timestamp_ms,
+            Some(2000.into())
+        );
+
+        supply_position.withdraw_shares(u128::MAX).unwrap();
+        assert!(supply_position.started_at_block_timestamp_ms.is_none());
+    }
+}
@@ -53,6 +53,10 @@ impl WithdrawalQueue {
         self.length
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
     pub fn get(&self, account_id: &AccountId) -> Option<u128> {
         self.entries
             .get(account_id)
@@ -161,7 +165,7 @@ impl WithdrawalQueue {
                 prev: self.queue_tail,
                 next: None,
             };
-            if self.queue_head == None {
+            if self.queue_head.is_none() {
                 self.queue_head = Some(node_id);
             }
             self.queue_tail = Some(node_id);
@@ -171,9 +175,9 @@ impl WithdrawalQueue {
         }
     }
 
-    pub fn iter(&self) -> WithdrawalQueueIter {
+    pub fn iter(&self) -> WithdrawalQueueIter<'_> {
         WithdrawalQueueIter {
-            withdrawal_queue: &self,
+            withdrawal_queue: self,
             next_node_id: self.queue_head,
         }
     }
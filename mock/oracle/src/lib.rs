@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+
+use near_sdk::{
+    collections::UnorderedMap,
+    env,
+    json_types::{I64, U64},
+    near, require, AccountId, PanicOnDefault,
+};
+use near_sdk_contract_tools::{owner::*, Owner};
+use templar_common::oracle::{Price, PriceIdentifier};
+
+/// Serves prices set by the owner, in the same format as the Pyth oracle.
+#[derive(PanicOnDefault, Owner)]
+#[near(contract_state)]
+pub struct Contract {
+    prices: UnorderedMap<PriceIdentifier, Price>,
+    /// While set, `list_prices` fails, as if the oracle were unreachable.
+    outage: bool,
+}
+
+#[near]
+impl Contract {
+    #[init]
+    pub fn new(owner_id: AccountId) -> Self {
+        let mut contract = Self {
+            prices: UnorderedMap::new(b"p"),
+            outage: false,
+        };
+
+        Owner::init(&mut contract, &owner_id);
+
+        contract
+    }
+
+    /// Sets a price with no confidence interval, published now.
+    pub fn set_price(&mut self, price_id: PriceIdentifier, price: I64, expo: i32) {
+        Self::require_owner();
+
+        self.prices.insert(
+            &price_id,
+            &Price {
+                price,
+                conf: 0.into(),
+                expo,
+                publish_time: (env::block_timestamp_ms() / 1000) as i64,
+            },
+        );
+    }
+
+    /// Sets a price exactly as given.
+    pub fn set_price_data(&mut self, price_id: PriceIdentifier, price: Price) {
+        Self::require_owner();
+
+        self.prices.insert(&price_id, &price);
+    }
+
+    /// In units of `10^expo`, like the price.
+    pub fn set_confidence(&mut self, price_id: PriceIdentifier, conf: U64) {
+        Self::require_owner();
+
+        let mut price = self.get_existing_price(&price_id);
+        price.conf = conf;
+        self.prices.insert(&price_id, &price);
+    }
+
+    /// Unix timestamp, in seconds.
+    pub fn set_publish_time(&mut self, price_id: PriceIdentifier, publish_time: i64) {
+        Self::require_owner();
+
+        let mut price = self.get_existing_price(&price_id);
+        price.publish_time = publish_time;
+        self.prices.insert(&price_id, &price);
+    }
+
+    /// The price feed is reported as missing.
+    pub fn remove_price(&mut self, price_id: PriceIdentifier) {
+        Self::require_owner();
+
+        self.prices.remove(&price_id);
+    }
+
+    pub fn set_outage(&mut self, outage: bool) {
+        Self::require_owner();
+
+        self.outage = outage;
+    }
+
+    pub fn get_price(&self, price_id: PriceIdentifier) -> Option<Price> {
+        require!(!self.outage, "Oracle is unavailable");
+
+        self.prices.get(&price_id)
+    }
+
+    pub fn list_prices(
+        &self,
+        price_ids: Vec<PriceIdentifier>,
+    ) -> HashMap<PriceIdentifier, Option<Price>> {
+        require!(!self.outage, "Oracle is unavailable");
+
+        price_ids
+            .into_iter()
+            .map(|price_id| {
+                let price = self.prices.get(&price_id);
+                (price_id, price)
+            })
+            .collect()
+    }
+}
+
+impl Contract {
+    fn get_existing_price(&self, price_id: &PriceIdentifier) -> Price {
+        self.prices
+            .get(price_id)
+            .unwrap_or_else(|| env::panic_str("Price not set"))
+    }
+}
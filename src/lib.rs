@@ -1,18 +1,33 @@
-use std::ops::{Deref, DerefMut};
+use std::{
+    collections::HashMap,
+    ops::{Deref, DerefMut},
+};
 
-use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+use near_contract_standards::fungible_token::{
+    core::FungibleTokenCore,
+    metadata::{FungibleTokenMetadata, FungibleTokenMetadataProvider, FT_METADATA_SPEC},
+    receiver::{ext_ft_receiver, FungibleTokenReceiver},
+    resolver::{ext_ft_resolver, FungibleTokenResolver},
+};
 use near_sdk::{
     env,
     json_types::{U128, U64},
-    near, require, AccountId, BorshStorageKey, PanicOnDefault, PromiseOrValue,
+    near, require, AccountId, BorshStorageKey, NearToken, PanicOnDefault, Promise, PromiseError,
+    PromiseOrValue, PromiseResult,
 };
+use near_sdk_contract_tools::{owner::*, rbac::*, standard::nep145::*, Owner, Rbac};
 use templar_common::{
     asset::FungibleAsset,
-    borrow::{BorrowPosition, BorrowStatus},
+    borrow::{BorrowPosition, BorrowPositionDetails, BorrowStatus},
+    fee::{Fee, TimeBasedFee},
     market::{
-        BorrowAssetMetrics, LiquidateMsg, Market, MarketConfiguration, MarketExternalInterface,
-        Nep141MarketDepositMessage, OraclePriceProof,
+        BorrowAssetMetrics, InterestRates, LiquidateMsg, LiquidationAmounts, LiquidationPolicy,
+        LiquidationSpread, Market, MarketAction, MarketConfiguration, MarketConfigurationError,
+        MarketEvent, MarketExternalInterface, Nep141MarketDepositMessage, OraclePriceProof,
+        PausedActions, RepayForMsg,
     },
+    oracle::{Price, PriceIdentifier},
+    rational::Rational,
     supply::SupplyPosition,
 };
 
@@ -22,22 +37,758 @@ enum StorageKey {
     Market,
 }
 
-#[derive(PanicOnDefault)]
+#[derive(BorshStorageKey)]
+#[near(serializers = [borsh])]
+pub enum Role {
+    /// May pause and unpause market actions.
+    Guardian,
+}
+
+/// Upper bound on the storage used by one account: its supply position,
+/// borrow position, withdrawal queue node, liquidator rewards, and storage
+/// balance record.
+const STORAGE_BYTES_PER_ACCOUNT: u128 = 2_000;
+
+/// Ownership can be transferred with `own_propose_owner` and
+/// `own_accept_owner`.
+#[derive(PanicOnDefault, Owner, Rbac)]
+#[rbac(roles = "Role")]
 #[near(contract_state)]
 pub struct Contract {
     pub market: Market,
+    pub paused: PausedActions,
 }
 
 #[near]
 impl Contract {
     #[init]
-    pub fn new(configuration: MarketConfiguration) -> Self {
-        Self {
+    pub fn new(owner_id: AccountId, configuration: MarketConfiguration) -> Self {
+        require_valid_configuration(&configuration);
+
+        let mut contract = Self {
             market: Market::new(StorageKey::Market, configuration),
+            paused: PausedActions::default(),
+        };
+
+        Owner::init(&mut contract, &owner_id);
+        contract.set_storage_balance_bounds(&StorageBalanceBounds {
+            min: env::storage_byte_cost().saturating_mul(STORAGE_BYTES_PER_ACCOUNT),
+            max: None,
+        });
+
+        contract
+    }
+
+    /// Continues [`MarketExternalInterface::borrow`] once prices have been
+    /// fetched from the price oracle.
+    #[private]
+    pub fn execute_borrow(
+        &mut self,
+        account_id: AccountId,
+        amount: U128,
+        #[callback_result] prices: Result<PriceList, PromiseError>,
+    ) -> PromiseOrValue<()> {
+        let oracle_price_proof = self.create_price_proof(prices);
+
+        // The market may have been paused, or availability may have changed,
+        // while the prices were fetched.
+        self.require_not_paused(MarketAction::Borrow);
+        require!(
+            amount <= self.market.get_borrow_asset_metrics().available,
+            "Insufficient borrow asset available",
+        );
+
+        // Apply origination fee during borrow by increasing liability during repayment.
+        // liable amount = amount to borrow + fee
+        let liable_amount = self
+            .configuration
+            .origination_fee
+            .of(amount.0)
+            .and_then(|fee| amount.0.checked_add(fee))
+            .unwrap_or_else(|| env::panic_str("Fee calculation failed"));
+
+        let borrow_position = self.record_borrow_position_borrow_asset_withdrawal(
+            &account_id,
+            liable_amount,
+            amount.0,
+        );
+
+        require!(
+            self.configuration
+                .is_healthy(&borrow_position, oracle_price_proof),
+            "Cannot borrow beyond MCR",
+        );
+
+        PromiseOrValue::Promise(
+            self.configuration
+                .borrow_asset
+                .transfer(account_id.clone(), amount.0)
+                .then(
+                    Self::ext(env::current_account_id()).resolve_borrow_transfer(
+                        account_id,
+                        liable_amount.into(),
+                        amount,
+                    ),
+                ),
+        )
+    }
+
+    /// Continues [`MarketExternalInterface::withdraw_collateral`] once
+    /// prices have been fetched from the price oracle.
+    #[private]
+    pub fn execute_withdraw_collateral(
+        &mut self,
+        account_id: AccountId,
+        amount: U128,
+        #[callback_result] prices: Result<PriceList, PromiseError>,
+    ) -> PromiseOrValue<()> {
+        let oracle_price_proof = self.create_price_proof(prices);
+
+        // The market may have been paused while the prices were fetched.
+        self.require_not_paused(MarketAction::WithdrawCollateral);
+
+        let borrow_position =
+            self.record_borrow_position_collateral_asset_withdrawal(&account_id, amount.0);
+
+        require!(
+            self.configuration
+                .is_healthy(&borrow_position, oracle_price_proof),
+            "Cannot withdraw collateral beyond MCR",
+        );
+
+        PromiseOrValue::Promise(self.transfer_collateral_withdrawal(account_id, amount))
+    }
+
+    /// Continues a liquidation received through `ft_on_transfer` once prices
+    /// have been fetched from the price oracle. Returns the amount of the
+    /// borrow asset to refund to the liquidator. If this fails, the whole
+    /// amount is refunded.
+    #[private]
+    pub fn execute_liquidation(
+        &mut self,
+        liquidator_id: AccountId,
+        account_id: AccountId,
+        amount: U128,
+        #[callback_result] prices: Result<PriceList, PromiseError>,
+    ) -> PromiseOrValue<U128> {
+        let oracle_price_proof = self.create_price_proof(prices);
+
+        // The market may have been paused while the prices were fetched.
+        self.require_not_paused(MarketAction::Liquidate);
+
+        let borrow_position = self
+            .market
+            .get_borrow_position(&account_id)
+            .unwrap_or_default();
+
+        require!(
+            self.configuration.borrow_status(
+                &borrow_position,
+                oracle_price_proof,
+                env::block_timestamp_ms(),
+            ) != BorrowStatus::Healthy,
+            "Borrow position cannot be liquidated at this price",
+        );
+
+        let LiquidationAmounts {
+            repay,
+            collateral,
+            margin,
+        } = self
+            .configuration
+            .liquidation_amounts(&borrow_position, amount.0, oracle_price_proof)
+            .unwrap_or_else(|| env::panic_str("Invalid oracle price"));
+
+        require!(repay > 0, "Nothing to liquidate");
+        require!(collateral > 0, "Nothing to liquidate");
+
+        let (repay, margin) = if collateral == borrow_position.collateral_asset_deposit.0 {
+            // The margin first covers any liability left without collateral.
+            let recovered = repay + margin;
+            let margin = self.record_full_liquidation(&account_id, &liquidator_id, recovered);
+            (recovered - margin, margin)
+        } else {
+            self.record_partial_liquidation(&account_id, &liquidator_id, repay, collateral);
+            (repay, margin)
+        };
+
+        PromiseOrValue::Promise(
+            self.configuration
+                .collateral_asset
+                .transfer(liquidator_id.clone(), collateral)
+                .then(
+                    Self::ext(env::current_account_id()).resolve_liquidation_transfer(
+                        liquidator_id,
+                        account_id,
+                        amount,
+                        repay.into(),
+                        collateral.into(),
+                        margin.into(),
+                        borrow_position.started_at_block_timestamp_ms,
+                    ),
+                ),
+        )
+    }
+
+    #[private]
+    pub fn resolve_borrow_transfer(
+        &mut self,
+        account_id: AccountId,
+        liable_amount: U128,
+        amount: U128,
+    ) {
+        if !is_transfer_success() {
+            self.revert_borrow_position_borrow_asset_withdrawal(
+                &account_id,
+                liable_amount.0,
+                amount.0,
+            );
+        }
+    }
+
+    #[private]
+    pub fn resolve_collateral_withdrawal_transfer(&mut self, account_id: AccountId, amount: U128) {
+        if !is_transfer_success() {
+            self.record_borrow_position_collateral_asset_deposit(&account_id, amount.0);
+        }
+    }
+
+    /// The withdrawal fee is only charged once the transfer succeeds. If it
+    /// fails, the withdrawal is reverted in full, but the request is not
+    /// queued again.
+    #[private]
+    pub fn resolve_withdrawal_transfer(
+        &mut self,
+        account_id: AccountId,
+        amount: U128,
+        shares: U128,
+        fee: U128,
+        started_at_block_timestamp_ms: U64,
+    ) {
+        if is_transfer_success() {
+            self.record_borrow_asset_withdrawal_fee(fee.0);
+        } else {
+            self.revert_supply_position_borrow_asset_withdrawal(
+                &account_id,
+                amount.0,
+                shares.0,
+                started_at_block_timestamp_ms.0,
+            );
+        }
+    }
+
+    /// Returns the amount of the borrow asset to refund to the liquidator.
+    /// The margin is only split, and any liability left without collateral
+    /// written off, once the collateral has been transferred. If the
+    /// transfer failed, the liquidation is reverted and the whole
+    /// amount is refunded.
+    #[private]
+    pub fn resolve_liquidation_transfer(
+        &mut self,
+        liquidator_id: AccountId,
+        account_id: AccountId,
+        amount: U128,
+        repay: U128,
+        collateral: U128,
+        margin: U128,
+        started_at_block_timestamp_ms: Option<U64>,
+    ) -> U128 {
+        if is_transfer_success() {
+            self.record_liquidation_margin(&liquidator_id, margin.0);
+            self.record_borrow_position_write_off(&account_id);
+            U128(amount.0 - repay.0 - margin.0)
+        } else {
+            self.revert_liquidation(
+                &account_id,
+                repay.0,
+                collateral.0,
+                started_at_block_timestamp_ms.map(|started_at| started_at.0),
+            );
+            amount
+        }
+    }
+
+    #[private]
+    pub fn resolve_liquidator_rewards_transfer(&mut self, account_id: AccountId, amount: U128) {
+        if !is_transfer_success() {
+            self.revert_liquidator_rewards_withdrawal(&account_id, amount.0);
+        }
+    }
+
+    #[private]
+    pub fn resolve_protocol_rewards_transfer(&mut self, amount: U128) {
+        if !is_transfer_success() {
+            self.revert_protocol_rewards_withdrawal(amount.0);
+        }
+    }
+
+    /// Refunds the unused part of a native NEAR deposit that was handled
+    /// asynchronously, or all of it if handling failed.
+    #[private]
+    pub fn resolve_native_deposit(&mut self, account_id: AccountId, amount: U128) -> U128 {
+        require!(
+            env::promise_results_count() == 1,
+            "Expected exactly one promise result",
+        );
+
+        let refund = match env::promise_result(0) {
+            PromiseResult::Successful(value) => {
+                near_sdk::serde_json::from_slice::<U128>(&value).unwrap_or(amount)
+            }
+            PromiseResult::Failed => amount,
+        };
+
+        if refund.0 > 0 {
+            FungibleAsset::Native.transfer(account_id, refund.0);
         }
+
+        refund
+    }
+}
+
+/// Native NEAR deposits, mirroring the [`Nep141MarketDepositMessage`]
+/// variants. The attached deposit is the amount, and any part of it that is
+/// not used is refunded.
+#[near]
+impl Contract {
+    #[payable]
+    pub fn supply_native(&mut self) -> PromiseOrValue<U128> {
+        self.execute_native_deposit(Nep141MarketDepositMessage::Supply)
+    }
+
+    #[payable]
+    pub fn collateralize_native(&mut self) -> PromiseOrValue<U128> {
+        self.execute_native_deposit(Nep141MarketDepositMessage::Collateralize)
+    }
+
+    #[payable]
+    pub fn repay_native(&mut self) -> PromiseOrValue<U128> {
+        self.execute_native_deposit(Nep141MarketDepositMessage::Repay)
+    }
+
+    #[payable]
+    pub fn repay_for_native(&mut self, account_id: AccountId) -> PromiseOrValue<U128> {
+        self.execute_native_deposit(Nep141MarketDepositMessage::RepayFor(RepayForMsg {
+            account_id,
+        }))
+    }
+
+    #[payable]
+    pub fn liquidate_native(&mut self, account_id: AccountId) -> PromiseOrValue<U128> {
+        self.execute_native_deposit(Nep141MarketDepositMessage::Liquidate(LiquidateMsg {
+            account_id,
+        }))
     }
 }
 
+/// Owner-only configuration updates. The assets and oracles cannot be
+/// changed after initialization.
+#[near]
+impl Contract {
+    pub fn set_origination_fee(&mut self, fee: Fee) {
+        self.update_configuration(|configuration| configuration.origination_fee = fee);
+    }
+
+    pub fn set_annual_maintenance_fee(&mut self, fee: Fee) {
+        self.update_configuration(|configuration| configuration.annual_maintenance_fee = fee);
+    }
+
+    pub fn set_withdrawal_fee(&mut self, fee: TimeBasedFee) {
+        self.update_configuration(|configuration| configuration.withdrawal_fee = fee);
+    }
+
+    /// Raising the minimum collateral ratio may make existing borrow
+    /// positions eligible for liquidation.
+    pub fn set_minimum_collateral_ratio_per_borrow(&mut self, ratio: Rational<u16>) {
+        self.update_configuration(|configuration| {
+            configuration.minimum_collateral_ratio_per_borrow = ratio;
+        });
+    }
+
+    pub fn set_maximum_borrow_asset_usage_ratio(&mut self, ratio: Rational<u16>) {
+        self.update_configuration(|configuration| {
+            configuration.maximum_borrow_asset_usage_ratio = ratio;
+        });
+    }
+
+    pub fn set_borrow_amount_limits(&mut self, minimum: U128, maximum: U128) {
+        self.update_configuration(|configuration| {
+            configuration.minimum_borrow_amount = minimum;
+            configuration.maximum_borrow_amount = maximum;
+        });
+    }
+
+    pub fn set_liquidation_spread(&mut self, spread: LiquidationSpread) {
+        self.update_configuration(|configuration| configuration.liquidation_spread = spread);
+    }
+
+    pub fn set_liquidation_policy(&mut self, policy: LiquidationPolicy) {
+        self.update_configuration(|configuration| configuration.liquidation_policy = policy);
+    }
+}
+
+/// Emergency pause controls.
+#[near]
+impl Contract {
+    /// Pauses `actions`, or [`MarketAction::DEFAULT_PAUSE`] if none are
+    /// specified. Callable by the owner or a guardian.
+    pub fn pause(&mut self, actions: Option<Vec<MarketAction>>) {
+        self.require_owner_or_guardian();
+
+        for action in actions.unwrap_or_else(|| MarketAction::DEFAULT_PAUSE.to_vec()) {
+            self.paused.set_paused(action, true);
+        }
+    }
+
+    /// Unpauses `actions`, or all actions if none are specified. Callable by
+    /// the owner or a guardian.
+    pub fn unpause(&mut self, actions: Option<Vec<MarketAction>>) {
+        self.require_owner_or_guardian();
+
+        match actions {
+            Some(actions) => {
+                for action in actions {
+                    self.paused.set_paused(action, false);
+                }
+            }
+            None => self.paused = PausedActions::default(),
+        }
+    }
+
+    pub fn get_paused_actions(&self) -> PausedActions {
+        self.paused.clone()
+    }
+
+    pub fn add_guardian(&mut self, account_id: AccountId) {
+        Self::require_owner();
+
+        self.add_role(&account_id, &Role::Guardian);
+    }
+
+    pub fn remove_guardian(&mut self, account_id: AccountId) {
+        Self::require_owner();
+
+        self.remove_role(&account_id, &Role::Guardian);
+    }
+
+    pub fn list_guardians(&self) -> Vec<AccountId> {
+        Self::iter_members_of(&Role::Guardian).collect()
+    }
+}
+
+impl Nep145ControllerInternal for Contract {
+    type ForceUnregisterHook = ();
+}
+
+/// NEP-145 storage management. Accounts must register by depositing at
+/// least the minimum storage balance before opening a position, which covers
+/// all of the storage that the account can use.
+#[near]
+impl Nep145 for Contract {
+    #[payable]
+    fn storage_deposit(
+        &mut self,
+        account_id: Option<AccountId>,
+        registration_only: Option<bool>,
+    ) -> StorageBalance {
+        let predecessor = env::predecessor_account_id();
+        let account_id = account_id.unwrap_or_else(|| predecessor.clone());
+        let attached = env::attached_deposit();
+
+        // Registering an account twice only refunds the deposit.
+        let amount = if registration_only.unwrap_or(false)
+            && self.get_storage_balance(&account_id).is_ok()
+        {
+            NearToken::from_yoctonear(0)
+        } else {
+            self.get_storage_balance_bounds()
+                .bound(attached, registration_only.unwrap_or(false))
+        };
+
+        let refund = attached.checked_sub(amount).unwrap_or_else(|| {
+            env::panic_str(&format!(
+                "Attached deposit {attached} is less than required {amount}"
+            ))
+        });
+
+        let storage_balance = if amount.is_zero() {
+            self.get_storage_balance(&account_id)
+                .unwrap_or_else(|e| env::panic_str(&e.to_string()))
+        } else {
+            self.deposit_to_storage_account(&account_id, amount)
+                .unwrap_or_else(|e| env::panic_str(&e.to_string()))
+        };
+
+        if !refund.is_zero() {
+            Promise::new(predecessor).transfer(refund);
+        }
+
+        self.with_withdrawable_available(storage_balance)
+    }
+
+    /// Only the balance above the minimum can be withdrawn.
+    #[payable]
+    fn storage_withdraw(&mut self, amount: Option<NearToken>) -> StorageBalance {
+        near_sdk::assert_one_yocto();
+
+        let predecessor = env::predecessor_account_id();
+        let balance = self
+            .get_storage_balance(&predecessor)
+            .unwrap_or_else(|e| env::panic_str(&e.to_string()));
+        let minimum = self.get_storage_balance_bounds().min;
+        let amount = amount.unwrap_or_else(|| balance.total.saturating_sub(minimum));
+
+        if amount.is_zero() {
+            return self.with_withdrawable_available(balance);
+        }
+
+        let balance = self
+            .withdraw_from_storage_account(&predecessor, amount)
+            .unwrap_or_else(|e| env::panic_str(&e.to_string()));
+
+        Promise::new(predecessor).transfer(amount);
+
+        self.with_withdrawable_available(balance)
+    }
+
+    /// Removes the account's empty positions and refunds its storage
+    /// balance. Accounts with open positions cannot unregister, even with
+    /// `force`, since that would forfeit their deposits and debts.
+    #[payable]
+    fn storage_unregister(&mut self, force: Option<bool>) -> bool {
+        near_sdk::assert_one_yocto();
+
+        let predecessor = env::predecessor_account_id();
+
+        if self.get_storage_balance(&predecessor).is_err() {
+            return false;
+        }
+
+        if force.unwrap_or(false) && self.has_open_positions(&predecessor) {
+            env::panic_str("Cannot force unregister an account with open positions");
+        }
+
+        self.remove_empty_positions(&predecessor);
+
+        let refund = self
+            .unregister_storage_account(&predecessor)
+            .unwrap_or_else(|e| env::panic_str(&e.to_string()));
+
+        Promise::new(predecessor).transfer(refund);
+
+        true
+    }
+
+    fn storage_balance_of(&self, account_id: AccountId) -> Option<StorageBalance> {
+        self.get_storage_balance(&account_id)
+            .ok()
+            .map(|balance| self.with_withdrawable_available(balance))
+    }
+
+    fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        self.get_storage_balance_bounds()
+    }
+}
+
+/// Supply shares as an NEP-141 token. Receivers must be registered with
+/// [`Nep145`].
+#[near]
+impl FungibleTokenCore for Contract {
+    #[payable]
+    fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>) {
+        near_sdk::assert_one_yocto();
+        self.require_not_paused(MarketAction::TransferShares);
+        self.require_registered(&receiver_id);
+
+        self.record_supply_shares_transfer(
+            &env::predecessor_account_id(),
+            &receiver_id,
+            amount.0,
+            memo.as_deref(),
+        );
+    }
+
+    #[payable]
+    fn ft_transfer_call(
+        &mut self,
+        receiver_id: AccountId,
+        amount: U128,
+        memo: Option<String>,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        near_sdk::assert_one_yocto();
+        self.require_not_paused(MarketAction::TransferShares);
+        self.require_registered(&receiver_id);
+
+        let sender_id = env::predecessor_account_id();
+
+        self.record_supply_shares_transfer(&sender_id, &receiver_id, amount.0, memo.as_deref());
+
+        PromiseOrValue::Promise(
+            ext_ft_receiver::ext(receiver_id.clone())
+                .ft_on_transfer(sender_id.clone(), amount, msg)
+                .then(
+                    ext_ft_resolver::ext(env::current_account_id()).ft_resolve_transfer(
+                        sender_id,
+                        receiver_id,
+                        amount,
+                    ),
+                ),
+        )
+    }
+
+    fn ft_total_supply(&self) -> U128 {
+        self.supply_shares.into()
+    }
+
+    fn ft_balance_of(&self, account_id: AccountId) -> U128 {
+        self.supply_positions
+            .get(&account_id)
+            .map_or(0, |supply_position| supply_position.shares.0)
+            .into()
+    }
+}
+
+#[near]
+impl FungibleTokenResolver for Contract {
+    /// Returns the amount of shares used by the receiver. Unused shares are
+    /// returned to the sender, as far as the receiver still holds them.
+    #[private]
+    fn ft_resolve_transfer(
+        &mut self,
+        sender_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+    ) -> U128 {
+        require!(
+            env::promise_results_count() == 1,
+            "Expected exactly one promise result",
+        );
+
+        let unused = match env::promise_result(0) {
+            PromiseResult::Successful(value) => near_sdk::serde_json::from_slice::<U128>(&value)
+                .map_or(amount.0, |unused| unused.0.min(amount.0)),
+            PromiseResult::Failed => amount.0,
+        };
+
+        let refund = unused.min(self.ft_balance_of(receiver_id.clone()).0);
+
+        if refund > 0 {
+            self.record_supply_shares_transfer(&receiver_id, &sender_id, refund, Some("refund"));
+        }
+
+        U128(amount.0 - refund)
+    }
+}
+
+#[near]
+impl FungibleTokenMetadataProvider for Contract {
+    /// Shares have the same number of decimals as the borrow asset.
+    fn ft_metadata(&self) -> FungibleTokenMetadata {
+        FungibleTokenMetadata {
+            spec: FT_METADATA_SPEC.to_string(),
+            name: format!(
+                "Templar supply shares of {}",
+                self.configuration.borrow_asset
+            ),
+            symbol: "TSHARE".to_string(),
+            icon: None,
+            reference: None,
+            reference_hash: None,
+            decimals: self
+                .configuration
+                .price_oracle_configuration
+                .borrow_asset_decimals,
+        }
+    }
+}
+
+type PriceList = HashMap<PriceIdentifier, Option<Price>>;
+
+impl Contract {
+    /// Applies `update` to the configuration if the result is valid. Only
+    /// callable by the owner.
+    fn update_configuration(&mut self, update: impl FnOnce(&mut MarketConfiguration)) {
+        Self::require_owner();
+
+        let mut configuration = self.configuration.clone();
+        update(&mut configuration);
+        require_valid_configuration(&configuration);
+
+        self.configuration = configuration;
+    }
+
+    fn require_owner_or_guardian(&self) {
+        let predecessor = env::predecessor_account_id();
+
+        require!(
+            Self::has_role(&predecessor, &Role::Guardian)
+                || Self::slot_owner().read().as_ref() == Some(&predecessor),
+            "Only the owner or a guardian can pause the market",
+        );
+    }
+
+    fn require_registered(&self, account_id: &AccountId) {
+        require!(
+            self.get_storage_balance(account_id).is_ok(),
+            "Account is not registered for storage",
+        );
+    }
+
+    fn require_not_paused(&self, action: MarketAction) {
+        require!(!self.paused.is_paused(action), "This action is paused");
+    }
+
+    /// Reports only the storage balance above the minimum as available, since
+    /// that is all [`StorageManagement::storage_withdraw`] allows.
+    fn with_withdrawable_available(&self, balance: StorageBalance) -> StorageBalance {
+        StorageBalance {
+            available: balance
+                .total
+                .saturating_sub(self.get_storage_balance_bounds().min),
+            ..balance
+        }
+    }
+
+    /// Sends a collateral withdrawal that has already been recorded.
+    fn transfer_collateral_withdrawal(&self, account_id: AccountId, amount: U128) -> Promise {
+        self.configuration
+            .collateral_asset
+            .transfer(account_id.clone(), amount.0)
+            .then(
+                Self::ext(env::current_account_id())
+                    .resolve_collateral_withdrawal_transfer(account_id, amount),
+            )
+    }
+
+    fn create_price_proof(&self, prices: Result<PriceList, PromiseError>) -> OraclePriceProof {
+        let prices = prices
+            .unwrap_or_else(|_| env::panic_str("Failed to fetch prices from the price oracle"));
+
+        self.configuration
+            .price_oracle_configuration
+            .create_price_proof(&prices, env::block_timestamp_ms())
+            .unwrap_or_else(|e| env::panic_str(&e.to_string()))
+    }
+}
+
+fn require_valid_configuration(configuration: &MarketConfiguration) {
+    configuration
+        .validate()
+        .unwrap_or_else(|e| env::panic_str(&e.to_string()));
+}
+
+/// Checks the result of the transfer that a `resolve_*_transfer` callback is
+/// attached to.
+fn is_transfer_success() -> bool {
+    require!(
+        env::promise_results_count() == 1,
+        "Expected exactly one promise result",
+    );
+
+    matches!(env::promise_result(0), PromiseResult::Successful(_))
+}
+
 impl Deref for Contract {
     type Target = Market;
 
@@ -63,7 +814,57 @@ impl FungibleTokenReceiver for Contract {
         let msg = near_sdk::serde_json::from_str::<Nep141MarketDepositMessage>(&msg)
             .unwrap_or_else(|_| env::panic_str("Invalid ft_on_transfer msg"));
 
-        let asset_id = FungibleAsset::Nep141(env::predecessor_account_id());
+        self.execute_deposit(
+            sender_id,
+            FungibleAsset::Nep141(env::predecessor_account_id()),
+            amount,
+            msg,
+        )
+    }
+}
+
+impl Contract {
+    /// Resolves to the amount refunded.
+    fn execute_native_deposit(&mut self, msg: Nep141MarketDepositMessage) -> PromiseOrValue<U128> {
+        let sender_id = env::predecessor_account_id();
+        let amount = U128(env::attached_deposit().as_yoctonear());
+
+        require!(amount.0 > 0, "Attached deposit must be greater than zero");
+
+        match self.execute_deposit(sender_id.clone(), FungibleAsset::Native, amount, msg) {
+            PromiseOrValue::Value(refund) => {
+                if refund.0 > 0 {
+                    FungibleAsset::Native.transfer(sender_id, refund.0);
+                }
+
+                PromiseOrValue::Value(refund)
+            }
+            PromiseOrValue::Promise(promise) => PromiseOrValue::Promise(promise.then(
+                Self::ext(env::current_account_id()).resolve_native_deposit(sender_id, amount),
+            )),
+        }
+    }
+
+    /// Handles a deposit of `amount` of `asset_id` from `ft_on_transfer` or
+    /// one of the native NEAR entry points. Resolves to the amount to be
+    /// refunded to `sender_id`.
+    fn execute_deposit(
+        &mut self,
+        sender_id: AccountId,
+        asset_id: FungibleAsset,
+        amount: U128,
+        msg: Nep141MarketDepositMessage,
+    ) -> PromiseOrValue<U128> {
+        if self.paused.is_paused(msg.action()) {
+            MarketEvent::PausedDepositRefund {
+                account_id: &sender_id,
+                action: msg.action(),
+                amount,
+            }
+            .emit();
+            // Refund the full amount.
+            return PromiseOrValue::Value(amount);
+        }
 
         match msg {
             Nep141MarketDepositMessage::Supply => {
@@ -71,6 +872,7 @@ impl FungibleTokenReceiver for Contract {
                     asset_id == self.configuration.borrow_asset,
                     "This market does not support supplying with this asset",
                 );
+                self.require_registered(&sender_id);
 
                 self.record_supply_position_borrow_asset_deposit(&sender_id, amount.0);
 
@@ -81,10 +883,8 @@ impl FungibleTokenReceiver for Contract {
                     asset_id == self.configuration.collateral_asset,
                     "This market does not support collateralization with this asset",
                 );
+                self.require_registered(&sender_id);
 
-                // TODO: This creates a borrow record implicitly. If we
-                // require a discrete "sign-up" step, we will need to add
-                // checks before this function call.
                 self.record_borrow_position_collateral_asset_deposit(&sender_id, amount.0);
 
                 PromiseOrValue::Value(U128(0))
@@ -95,45 +895,41 @@ impl FungibleTokenReceiver for Contract {
                     "This market does not support repayment with this asset",
                 );
 
-                // TODO: This function *errors* on overpayment. Instead, add a
-                // check before and only repay the maximum, then return the excess.
-                self.record_borrow_position_borrow_asset_repay(&sender_id, amount.0);
+                let repaid = self.record_borrow_position_borrow_asset_repay(&sender_id, amount.0);
 
-                PromiseOrValue::Value(U128(0))
+                PromiseOrValue::Value(U128(amount.0 - repaid))
             }
-            Nep141MarketDepositMessage::Liquidate(LiquidateMsg {
-                account_id,
-                oracle_price_proof,
-            }) => {
+            Nep141MarketDepositMessage::RepayFor(RepayForMsg { account_id }) => {
                 require!(
                     asset_id == self.configuration.borrow_asset,
-                    "This market does not support liquidation with this asset",
-                );
-                require!(
-                    sender_id == self.configuration.liquidator_account_id,
-                    "Account not authorized to perform liquidations",
+                    "This market does not support repayment with this asset",
                 );
 
-                let borrow_position = self
-                    .market
-                    .get_borrow_position(&account_id)
-                    .unwrap_or_default();
+                let repaid = self.record_borrow_position_borrow_asset_repay(&account_id, amount.0);
 
+                PromiseOrValue::Value(U128(amount.0 - repaid))
+            }
+            Nep141MarketDepositMessage::Liquidate(LiquidateMsg { account_id }) => {
                 require!(
-                    !self
-                        .configuration
-                        .is_healthy(&borrow_position, oracle_price_proof),
-                    "Borrow position cannot be liquidated at this price",
+                    asset_id == self.configuration.borrow_asset,
+                    "This market does not support liquidation with this asset",
                 );
+                require!(
+                    self.configuration.liquidation_policy.is_allowed(&sender_id),
+                    "Account not authorized to perform liquidations",
+                );
+                // The liquidator may be owed rewards.
+                self.require_registered(&sender_id);
 
-                // TODO: Do we need to check the value of the amount recovered?
-                // We have the price data available in `oracle_price_proof`...
-                self.record_full_liquidation(&account_id, amount.0);
-
-                // TODO: (cont'd from above) This would allow us to calculate
-                // the amount that "should" be recovered and refund the
-                // liquidator any excess.
-                PromiseOrValue::Value(U128(0))
+                PromiseOrValue::Promise(
+                    self.configuration
+                        .price_oracle_configuration
+                        .request_prices()
+                        .then(
+                            Self::ext(env::current_account_id())
+                                .execute_liquidation(sender_id, account_id, amount),
+                        ),
+                )
             }
         }
     }
@@ -145,18 +941,26 @@ impl MarketExternalInterface for Contract {
         self.configuration.clone()
     }
 
+    fn validate_configuration(
+        &self,
+        configuration: MarketConfiguration,
+    ) -> Option<MarketConfigurationError> {
+        configuration.validate().err()
+    }
+
     fn get_borrow_asset_metrics(&self) -> BorrowAssetMetrics {
-        BorrowAssetMetrics::calculate(
-            self.borrow_asset_deposited,
-            self.borrow_asset_balance,
-            self.configuration.maximum_borrow_asset_usage_ratio.upcast(),
-        )
+        self.market.get_borrow_asset_metrics()
     }
 
     fn get_collateral_asset_balance(&self) -> U128 {
         self.collateral_asset_balance.into()
     }
 
+    fn get_interest_rates(&self) -> InterestRates {
+        self.market.get_interest_rates()
+    }
+
+    #[allow(unused_variables)]
     fn report_remote_asset_balance(&mut self, address: String, asset: String, amount: U128) {
         todo!()
     }
@@ -182,7 +986,7 @@ impl MarketExternalInterface for Contract {
     }
 
     fn get_borrow_position(&self, account_id: AccountId) -> Option<BorrowPosition> {
-        self.borrow_positions.get(&account_id)
+        self.market.get_borrow_position(&account_id)
     }
 
     fn get_borrow_status(
@@ -190,20 +994,25 @@ impl MarketExternalInterface for Contract {
         account_id: AccountId,
         oracle_price_proof: OraclePriceProof,
     ) -> Option<BorrowStatus> {
-        let Some(borrow_position) = self.borrow_positions.get(&account_id) else {
-            return None;
-        };
+        let borrow_position = self.market.get_borrow_position(&account_id)?;
 
-        if self
-            .configuration
-            .is_healthy(&borrow_position, oracle_price_proof)
-        {
-            Some(BorrowStatus::Healthy)
-        } else {
-            Some(BorrowStatus::Liquidation)
-        }
+        Some(self.configuration.borrow_status(
+            &borrow_position,
+            oracle_price_proof,
+            env::block_timestamp_ms(),
+        ))
+    }
+
+    fn get_borrow_position_details(
+        &self,
+        account_id: AccountId,
+        oracle_price_proof: OraclePriceProof,
+    ) -> Option<BorrowPositionDetails> {
+        self.market
+            .get_borrow_position_details(&account_id, oracle_price_proof)
     }
 
+    #[allow(unused_variables)]
     fn get_collateral_asset_deposit_address_for(
         &self,
         account_id: AccountId,
@@ -212,76 +1021,248 @@ impl MarketExternalInterface for Contract {
         todo!()
     }
 
+    #[allow(unused_variables)]
     fn initialize_borrow(&mut self, borrow_asset_amount: U128, collateral_asset_amount: U128) {
         todo!()
     }
 
-    fn borrow(&mut self, amount: U128, oracle_price_proof: OraclePriceProof) -> PromiseOrValue<()> {
+    fn borrow(&mut self, amount: U128) -> PromiseOrValue<()> {
+        self.require_not_paused(MarketAction::Borrow);
+        self.require_registered(&env::predecessor_account_id());
+
         require!(amount.0 > 0, "Borrow amount must be greater than zero");
+        require!(
+            amount >= self.configuration.minimum_borrow_amount,
+            "Borrow amount is less than the minimum borrow amount",
+        );
+        require!(
+            amount <= self.configuration.maximum_borrow_amount,
+            "Borrow amount is greater than the maximum borrow amount",
+        );
+        require!(
+            amount <= self.market.get_borrow_asset_metrics().available,
+            "Insufficient borrow asset available",
+        );
 
-        let account_id = env::predecessor_account_id();
+        PromiseOrValue::Promise(
+            self.configuration
+                .price_oracle_configuration
+                .request_prices()
+                .then(
+                    Self::ext(env::current_account_id())
+                        .execute_borrow(env::predecessor_account_id(), amount),
+                ),
+        )
+    }
 
-        // Apply origination fee during borrow by increasing liability during repayment.
-        // liable amount = amount to borrow + fee
-        let liable_amount = self
-            .configuration
-            .origination_fee
-            .of(amount.0)
-            .and_then(|fee| amount.0.checked_add(fee))
-            .unwrap_or_else(|| env::panic_str("Fee calculation failed"));
+    fn withdraw_collateral(&mut self, amount: U128) -> PromiseOrValue<()> {
+        self.require_not_paused(MarketAction::WithdrawCollateral);
+        require!(amount.0 > 0, "Withdrawal amount must be greater than zero");
 
-        let borrow_position = self.record_borrow_position_borrow_asset_withdrawal(
-            &account_id,
-            liable_amount,
-            amount.0,
-        );
+        let account_id = env::predecessor_account_id();
 
-        require!(
-            self.configuration
-                .is_healthy(&borrow_position, oracle_price_proof),
-            "Cannot borrow beyond MCR",
-        );
+        // Without a liability, the position stays healthy whatever the
+        // prices are.
+        if self
+            .market
+            .get_borrow_position(&account_id)
+            .is_some_and(|borrow_position| borrow_position.borrow_asset_liability.0 == 0)
+        {
+            self.record_borrow_position_collateral_asset_withdrawal(&account_id, amount.0);
+            return PromiseOrValue::Promise(
+                self.transfer_collateral_withdrawal(account_id, amount),
+            );
+        }
 
         PromiseOrValue::Promise(
             self.configuration
-                .borrow_asset
-                .transfer(env::predecessor_account_id(), amount.0),
+                .price_oracle_configuration
+                .request_prices()
+                .then(
+                    Self::ext(env::current_account_id())
+                        .execute_withdraw_collateral(account_id, amount),
+                ),
         )
     }
 
     fn get_supply_position(&self, account_id: AccountId) -> Option<SupplyPosition> {
-        self.supply_positions.get(&account_id)
+        self.market.get_supply_position(&account_id)
+    }
+
+    fn convert_to_shares(&self, amount: U128) -> U128 {
+        self.market.convert_to_shares(amount.0).into()
+    }
+
+    fn convert_to_assets(&self, shares: U128) -> U128 {
+        self.market.convert_to_assets(shares.0).into()
     }
 
     fn queue_withdrawal(&mut self, amount: U128) {
         // TODO: Check that amount is a sane value? i.e. within the amount actually deposited?
         let predecessor = env::predecessor_account_id();
+        self.require_registered(&predecessor);
         self.withdrawal_queue.remove(&predecessor);
         self.withdrawal_queue
             .insert_or_update(&predecessor, amount.0);
+
+        MarketEvent::WithdrawalRequest {
+            account_id: &predecessor,
+            amount,
+        }
+        .emit();
     }
 
     fn cancel_withdrawal(&mut self) {
-        self.withdrawal_queue.remove(&env::predecessor_account_id());
+        let predecessor = env::predecessor_account_id();
+
+        if self.withdrawal_queue.remove(&predecessor).is_some() {
+            MarketEvent::WithdrawalRequestCancel {
+                account_id: &predecessor,
+            }
+            .emit();
+        }
     }
 
-    fn process_next_withdrawal(&mut self) {
-        todo!()
+    fn process_next_withdrawal(&mut self) -> PromiseOrValue<()> {
+        self.require_not_paused(MarketAction::Withdraw);
+
+        let (account_id, requested_amount) = self
+            .withdrawal_queue
+            .peek()
+            .unwrap_or_else(|| env::panic_str("Withdrawal queue is empty"));
+
+        let Some(supply_position) = self.supply_positions.get(&account_id) else {
+            // Nothing left to withdraw.
+            self.withdrawal_queue.pop();
+            MarketEvent::WithdrawalRequestFill {
+                account_id: &account_id,
+                amount: U128(0),
+                remaining: U128(0),
+            }
+            .emit();
+            return PromiseOrValue::Value(());
+        };
+
+        // Pending interest raises the value of the shares.
+        self.accrue_borrow_interest();
+
+        // Requests are not checked when they are queued, and the position may
+        // have changed since then.
+        let requested_amount =
+            requested_amount.min(self.market.convert_to_assets(supply_position.shares.0));
+        let amount = requested_amount.min(self.borrow_asset_balance);
+
+        if amount == requested_amount {
+            self.withdrawal_queue.pop();
+        } else {
+            require!(
+                amount > 0,
+                "Insufficient borrow asset balance to process withdrawal"
+            );
+            self.withdrawal_queue
+                .insert_or_update(&account_id, requested_amount - amount);
+        }
+
+        MarketEvent::WithdrawalRequestFill {
+            account_id: &account_id,
+            amount: U128(amount),
+            remaining: U128(requested_amount - amount),
+        }
+        .emit();
+
+        if amount == 0 {
+            return PromiseOrValue::Value(());
+        }
+
+        let fee = self
+            .configuration
+            .withdrawal_fee
+            .of(
+                amount,
+                supply_position.deposit_duration_ms(env::block_timestamp_ms()),
+            )
+            .unwrap_or_else(|| env::panic_str("Fee calculation failed"))
+            .min(amount);
+
+        let started_at_block_timestamp_ms = supply_position
+            .started_at_block_timestamp_ms
+            .map_or_else(env::block_timestamp_ms, |started_at| started_at.0);
+
+        let shares = self.record_supply_position_borrow_asset_withdrawal(&account_id, amount);
+
+        let transfer_amount = amount - fee;
+
+        if transfer_amount == 0 {
+            self.record_borrow_asset_withdrawal_fee(fee);
+            return PromiseOrValue::Value(());
+        }
+
+        PromiseOrValue::Promise(
+            self.configuration
+                .borrow_asset
+                .transfer(account_id.clone(), transfer_amount)
+                .then(
+                    Self::ext(env::current_account_id()).resolve_withdrawal_transfer(
+                        account_id,
+                        amount.into(),
+                        shares.into(),
+                        fee.into(),
+                        started_at_block_timestamp_ms.into(),
+                    ),
+                ),
+        )
     }
 
-    fn harvest_yield(&mut self) {
-        todo!()
+    fn harvest_yield(&mut self) {}
+
+    fn withdraw_supply_position_rewards(&mut self, _amount: U128) -> PromiseOrValue<()> {
+        env::panic_str("withdraw_supply_position_rewards is deprecated; use queue_withdrawal");
     }
 
-    fn withdraw_supply_position_rewards(&mut self, amount: U128) {
-        todo!()
+    fn get_liquidator_rewards(&self, account_id: AccountId) -> U128 {
+        self.liquidator_rewards.get(&account_id).unwrap_or(0).into()
     }
 
-    fn withdraw_liquidator_rewards(&mut self, amount: U128) {
-        todo!()
+    fn get_protocol_rewards(&self) -> U128 {
+        self.protocol_rewards.into()
     }
 
-    fn withdraw_protocol_rewards(&mut self, amount: U128) {
-        todo!()
+    fn withdraw_liquidator_rewards(&mut self, amount: U128) -> PromiseOrValue<()> {
+        self.require_not_paused(MarketAction::WithdrawRewards);
+        require!(amount.0 > 0, "Withdrawal amount must be greater than zero");
+
+        let account_id = env::predecessor_account_id();
+
+        self.record_liquidator_rewards_withdrawal(&account_id, amount.0);
+
+        PromiseOrValue::Promise(
+            self.configuration
+                .borrow_asset
+                .transfer(account_id.clone(), amount.0)
+                .then(
+                    Self::ext(env::current_account_id())
+                        .resolve_liquidator_rewards_transfer(account_id, amount),
+                ),
+        )
+    }
+
+    fn withdraw_protocol_rewards(&mut self, amount: U128) -> PromiseOrValue<()> {
+        self.require_not_paused(MarketAction::WithdrawRewards);
+        require!(amount.0 > 0, "Withdrawal amount must be greater than zero");
+        require!(
+            env::predecessor_account_id() == self.configuration.protocol_account_id,
+            "Account not authorized to withdraw protocol rewards",
+        );
+
+        self.record_protocol_rewards_withdrawal(amount.0);
+
+        PromiseOrValue::Promise(
+            self.configuration
+                .borrow_asset
+                .transfer(self.configuration.protocol_account_id.clone(), amount.0)
+                .then(
+                    Self::ext(env::current_account_id()).resolve_protocol_rewards_transfer(amount),
+                ),
+        )
     }
 }
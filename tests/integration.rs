@@ -1,17 +1,27 @@
 use near_sdk::{
-    json_types::{U128, U64},
+    json_types::{I64, U128},
+    serde::Serialize,
     serde_json::json,
     AccountId, AccountIdRef, NearToken,
 };
+use near_sdk_contract_tools::standard::nep145::{StorageBalance, StorageBalanceBounds};
 use near_workspaces::{
-    network::Sandbox, operations::Function, prelude::TopLevelAccountCreator, Account, Contract,
-    DevNetwork, Worker,
+    network::Sandbox, operations::Function, prelude::TopLevelAccountCreator,
+    result::ExecutionFinalResult, Account, Contract, DevNetwork, Worker,
 };
 use templar_common::{
     asset::FungibleAsset,
+    borrow::{BorrowPosition, BorrowPositionDetails, BorrowStatus},
     fee::{Fee, TimeBasedFee, TimeBasedFeeFunction},
-    market::{LiquidationSpread, MarketConfiguration},
+    interest_rate_model::InterestRateModel,
+    market::{
+        BorrowAssetMetrics, LiquidateMsg, LiquidationPolicy, LiquidationSpread, MarketAction,
+        MarketConfiguration, MarketConfigurationError, Nep141MarketDepositMessage,
+        OraclePriceProof, PausedActions, RepayForMsg,
+    },
+    oracle::{PriceIdentifier, PriceOracleConfiguration},
     rational::Rational,
+    supply::SupplyPosition,
 };
 
 // ===== UTIL FUNCTIONS =====
@@ -33,6 +43,7 @@ macro_rules! accounts {
     };
 }
 
+#[allow(dead_code)]
 async fn deploy_mock_ft(
     worker: &Worker<Sandbox>,
     account_id: AccountId,
@@ -61,6 +72,49 @@ async fn deploy_mock_ft(
     contract
 }
 
+const COLLATERAL_ASSET_PRICE_ID: &str =
+    "c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0";
+const BORROW_ASSET_PRICE_ID: &str =
+    "b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0";
+
+async fn deploy_price_oracle(worker: &Worker<Sandbox>) -> Contract {
+    let contract = worker
+        .dev_deploy(
+            &near_workspaces::compile_project("./mock/oracle")
+                .await
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    contract
+        .call("new")
+        .args_json(json!({
+            "owner_id": contract.id(),
+        }))
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+
+    contract
+}
+
+/// Sets the price of one whole unit of an asset to `price * 10^expo`.
+async fn set_price(price_oracle: &Contract, price_id: &str, price: i64, expo: i32) {
+    price_oracle
+        .call("set_price")
+        .args_json(json!({
+            "price_id": price_id,
+            "price": I64(price),
+            "expo": expo,
+        }))
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+}
+
 fn market_configuration(
     borrow_asset_id: AccountId,
     collateral_asset_id: AccountId,
@@ -70,11 +124,24 @@ fn market_configuration(
         borrow_asset: FungibleAsset::Nep141(borrow_asset_id),
         collateral_asset: FungibleAsset::Nep141(collateral_asset_id),
         balance_oracle_account_id: "balance_oracle".parse().unwrap(),
-        liquidator_account_id,
+        price_oracle_configuration: PriceOracleConfiguration {
+            account_id: "price_oracle".parse().unwrap(),
+            collateral_asset_price_id: PriceIdentifier(COLLATERAL_ASSET_PRICE_ID.to_string()),
+            collateral_asset_decimals: 0,
+            borrow_asset_price_id: PriceIdentifier(BORROW_ASSET_PRICE_ID.to_string()),
+            borrow_asset_decimals: 0,
+            price_maximum_age_s: 60,
+            maximum_confidence_ratio: Rational::new(1, 100),
+        },
+        liquidation_policy: LiquidationPolicy::Single(liquidator_account_id.clone()),
+        protocol_account_id: liquidator_account_id,
         minimum_collateral_ratio_per_borrow: Rational::new(120, 100),
         maximum_borrow_asset_usage_ratio: Rational::new(99, 100),
         origination_fee: Fee::Proportional(Rational::new(1, 100)),
         annual_maintenance_fee: Fee::Flat(0.into()),
+        interest_rate_model: InterestRateModel::Fixed {
+            rate: Rational::new(0, 1),
+        },
         maximum_borrow_duration: None,
         minimum_borrow_amount: 1.into(),
         maximum_borrow_amount: u128::MAX.into(),
@@ -88,6 +155,8 @@ fn market_configuration(
             liquidator: 1.into(),
             protocol: 1.into(),
         },
+        liquidation_close_factor: Rational::new(50, 100),
+        liquidation_bonus: Rational::new(5, 100),
     }
 }
 
@@ -98,6 +167,7 @@ async fn setup_market(worker: &Worker<Sandbox>, configuration: MarketConfigurati
     contract
         .call("new")
         .args_json(json!({
+            "owner_id": contract.id(),
             "configuration": configuration,
         }))
         .transact()
@@ -137,6 +207,60 @@ async fn deploy_ft(
         .unwrap();
 }
 
+async fn storage_deposit(account: &Account, contract_id: &AccountId, account_id: &AccountIdRef) {
+    account
+        .call(contract_id, "storage_deposit")
+        .args_json(json!({
+            "account_id": account_id,
+        }))
+        .deposit(NearToken::from_millinear(100))
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+async fn ft_transfer_call(
+    account: &Account,
+    token_id: &AccountId,
+    receiver_id: &AccountIdRef,
+    amount: u128,
+    msg: impl Serialize,
+) {
+    account
+        .call(token_id, "ft_transfer_call")
+        .args_json(json!({
+            "receiver_id": receiver_id,
+            "amount": U128(amount),
+            "msg": near_sdk::serde_json::to_string(&msg).unwrap(),
+        }))
+        .deposit(NearToken::from_yoctonear(1))
+        .max_gas()
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+async fn ft_balance_of(account: &Account, token_id: &AccountId) -> u128 {
+    account
+        .view(token_id, "ft_balance_of")
+        .args_json(json!({
+            "account_id": account.id(),
+        }))
+        .await
+        .unwrap()
+        .json::<U128>()
+        .unwrap()
+        .0
+}
+
+/// Asserts that `result` failed with a panic containing `message`.
+fn assert_failure(result: ExecutionFinalResult, message: &str) {
+    let failure = format!("{:?}", result.into_result().unwrap_err());
+    assert!(failure.contains(message), "{failure}");
+}
+
 // ===== TESTS =====
 
 #[tokio::test]
@@ -197,3 +321,1672 @@ async fn test_create_market() {
         Rational::new(120, 100)
     );
 }
+
+#[tokio::test]
+async fn test_supply_and_withdraw() {
+    let worker = near_workspaces::sandbox().await.unwrap();
+    accounts!(
+        worker,
+        owner_user,
+        supply_user,
+        collateral_asset,
+        borrow_asset
+    );
+    let contract = setup_market(
+        &worker,
+        market_configuration(
+            borrow_asset.id().clone(),
+            collateral_asset.id().clone(),
+            owner_user.id().clone(),
+        ),
+    )
+    .await;
+    deploy_ft(
+        &borrow_asset,
+        "Borrow Asset",
+        "BORROW",
+        supply_user.id(),
+        1000,
+    )
+    .await;
+    storage_deposit(&supply_user, borrow_asset.id(), contract.id()).await;
+    storage_deposit(&supply_user, contract.id(), supply_user.id()).await;
+
+    ft_transfer_call(
+        &supply_user,
+        borrow_asset.id(),
+        contract.id(),
+        1000,
+        Nep141MarketDepositMessage::Supply,
+    )
+    .await;
+
+    assert_eq!(ft_balance_of(&supply_user, borrow_asset.id()).await, 0);
+
+    supply_user
+        .call(contract.id(), "queue_withdrawal")
+        .args_json(json!({
+            "amount": U128(600),
+        }))
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+
+    owner_user
+        .call(contract.id(), "process_next_withdrawal")
+        .args_json(json!({}))
+        .max_gas()
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(ft_balance_of(&supply_user, borrow_asset.id()).await, 600);
+
+    let supply_position = contract
+        .view("get_supply_position")
+        .args_json(json!({
+            "account_id": supply_user.id(),
+        }))
+        .await
+        .unwrap()
+        .json::<Option<SupplyPosition>>()
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(supply_position.shares.0, 400);
+    assert_eq!(ft_balance_of(&supply_user, contract.id()).await, 400);
+
+    // The deprecated reward functions are kept for compatibility, but do
+    // not withdraw anything.
+    supply_user
+        .call(contract.id(), "harvest_yield")
+        .args_json(json!({}))
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+    assert_failure(
+        supply_user
+            .call(contract.id(), "withdraw_supply_position_rewards")
+            .args_json(json!({
+                "amount": U128(100),
+            }))
+            .transact()
+            .await
+            .unwrap(),
+        "withdraw_supply_position_rewards is deprecated; use queue_withdrawal",
+    );
+
+    assert_eq!(ft_balance_of(&supply_user, borrow_asset.id()).await, 600);
+    assert_eq!(ft_balance_of(&supply_user, contract.id()).await, 400);
+}
+
+#[tokio::test]
+async fn test_supply_shares() {
+    let worker = near_workspaces::sandbox().await.unwrap();
+    accounts!(
+        worker,
+        owner_user,
+        supply_user,
+        share_user,
+        collateral_asset,
+        borrow_asset
+    );
+    let contract = setup_market(
+        &worker,
+        market_configuration(
+            borrow_asset.id().clone(),
+            collateral_asset.id().clone(),
+            owner_user.id().clone(),
+        ),
+    )
+    .await;
+    deploy_ft(
+        &borrow_asset,
+        "Borrow Asset",
+        "BORROW",
+        supply_user.id(),
+        1000,
+    )
+    .await;
+    storage_deposit(&supply_user, borrow_asset.id(), contract.id()).await;
+    storage_deposit(&share_user, borrow_asset.id(), share_user.id()).await;
+    storage_deposit(&supply_user, contract.id(), supply_user.id()).await;
+
+    ft_transfer_call(
+        &supply_user,
+        borrow_asset.id(),
+        contract.id(),
+        1000,
+        Nep141MarketDepositMessage::Supply,
+    )
+    .await;
+
+    // The first shares are minted one-to-one.
+    assert_eq!(ft_balance_of(&supply_user, contract.id()).await, 1000);
+
+    let convert = |method: &'static str, amount: u128| {
+        let contract = contract.clone();
+        async move {
+            contract
+                .view(method)
+                .args_json(json!({
+                    "amount": U128(amount),
+                    "shares": U128(amount),
+                }))
+                .await
+                .unwrap()
+                .json::<U128>()
+                .unwrap()
+                .0
+        }
+    };
+
+    assert_eq!(convert("convert_to_shares", 300).await, 300);
+    assert_eq!(convert("convert_to_assets", 300).await, 300);
+
+    let transfer_shares = || async {
+        supply_user
+            .call(contract.id(), "ft_transfer")
+            .args_json(json!({
+                "receiver_id": share_user.id(),
+                "amount": U128(300),
+            }))
+            .deposit(NearToken::from_yoctonear(1))
+            .transact()
+            .await
+            .unwrap()
+    };
+
+    // The receiver must be registered.
+    assert!(transfer_shares().await.is_failure());
+
+    storage_deposit(&share_user, contract.id(), share_user.id()).await;
+    transfer_shares().await.unwrap();
+
+    assert_eq!(ft_balance_of(&supply_user, contract.id()).await, 700);
+    assert_eq!(ft_balance_of(&share_user, contract.id()).await, 300);
+
+    // Shares can be redeemed by whoever holds them.
+    share_user
+        .call(contract.id(), "queue_withdrawal")
+        .args_json(json!({
+            "amount": U128(300),
+        }))
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+
+    owner_user
+        .call(contract.id(), "process_next_withdrawal")
+        .args_json(json!({}))
+        .max_gas()
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(ft_balance_of(&share_user, borrow_asset.id()).await, 300);
+    assert_eq!(ft_balance_of(&share_user, contract.id()).await, 0);
+
+    let total_supply = contract
+        .view("ft_total_supply")
+        .await
+        .unwrap()
+        .json::<U128>()
+        .unwrap();
+
+    assert_eq!(total_supply.0, 700);
+}
+
+#[tokio::test]
+async fn test_withdrawal_transfer_failure_is_rolled_back() {
+    let worker = near_workspaces::sandbox().await.unwrap();
+    accounts!(
+        worker,
+        owner_user,
+        supply_user,
+        share_user,
+        collateral_asset,
+        borrow_asset
+    );
+    let mut configuration = market_configuration(
+        borrow_asset.id().clone(),
+        collateral_asset.id().clone(),
+        owner_user.id().clone(),
+    );
+    configuration.withdrawal_fee = TimeBasedFee {
+        fee: Fee::Flat(10.into()),
+        duration: 1_000_000_000.into(),
+        behavior: TimeBasedFeeFunction::Fixed,
+    };
+    let contract = setup_market(&worker, configuration).await;
+    deploy_ft(
+        &borrow_asset,
+        "Borrow Asset",
+        "BORROW",
+        supply_user.id(),
+        1000,
+    )
+    .await;
+    storage_deposit(&supply_user, borrow_asset.id(), contract.id()).await;
+    storage_deposit(&supply_user, contract.id(), supply_user.id()).await;
+    storage_deposit(&share_user, contract.id(), share_user.id()).await;
+
+    ft_transfer_call(
+        &supply_user,
+        borrow_asset.id(),
+        contract.id(),
+        1000,
+        Nep141MarketDepositMessage::Supply,
+    )
+    .await;
+
+    supply_user
+        .call(contract.id(), "ft_transfer")
+        .args_json(json!({
+            "receiver_id": share_user.id(),
+            "amount": U128(300),
+        }))
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+
+    let get_supply_position = || async {
+        contract
+            .view("get_supply_position")
+            .args_json(json!({
+                "account_id": share_user.id(),
+            }))
+            .await
+            .unwrap()
+            .json::<Option<SupplyPosition>>()
+            .unwrap()
+            .unwrap()
+    };
+
+    let supply_position_before = get_supply_position().await;
+
+    share_user
+        .call(contract.id(), "queue_withdrawal")
+        .args_json(json!({
+            "amount": U128(300),
+        }))
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+
+    // share_user is not registered with the borrow asset, so the transfer
+    // fails.
+    let result = owner_user
+        .call(contract.id(), "process_next_withdrawal")
+        .args_json(json!({}))
+        .max_gas()
+        .transact()
+        .await
+        .unwrap();
+
+    assert!(!result.receipt_failures().is_empty());
+
+    // The shares are restored with their original start, and the fee is not
+    // charged.
+    let supply_position = get_supply_position().await;
+    assert_eq!(supply_position.shares.0, 300);
+    assert_eq!(
+        supply_position.started_at_block_timestamp_ms,
+        supply_position_before.started_at_block_timestamp_ms,
+    );
+    assert_eq!(ft_balance_of(&share_user, contract.id()).await, 300);
+
+    let metrics = contract
+        .view("get_borrow_asset_metrics")
+        .args_json(json!({}))
+        .await
+        .unwrap()
+        .json::<BorrowAssetMetrics>()
+        .unwrap();
+
+    assert_eq!(metrics.used.0, 0);
+    assert_eq!(metrics.deposited.0, 1000);
+
+    let total_supply = contract
+        .view("ft_total_supply")
+        .await
+        .unwrap()
+        .json::<U128>()
+        .unwrap();
+
+    assert_eq!(total_supply.0, 1000);
+}
+
+#[tokio::test]
+async fn test_borrow_transfer_failure_is_rolled_back() {
+    let worker = near_workspaces::sandbox().await.unwrap();
+    accounts!(
+        worker,
+        owner_user,
+        supply_user,
+        borrow_user,
+        collateral_asset,
+        borrow_asset
+    );
+    let price_oracle = deploy_price_oracle(&worker).await;
+    let mut configuration = market_configuration(
+        borrow_asset.id().clone(),
+        collateral_asset.id().clone(),
+        owner_user.id().clone(),
+    );
+    configuration.price_oracle_configuration.account_id = price_oracle.id().clone();
+    let contract = setup_market(&worker, configuration).await;
+    set_price(&price_oracle, COLLATERAL_ASSET_PRICE_ID, 1, 0).await;
+    set_price(&price_oracle, BORROW_ASSET_PRICE_ID, 1, 0).await;
+    deploy_ft(
+        &borrow_asset,
+        "Borrow Asset",
+        "BORROW",
+        supply_user.id(),
+        1000,
+    )
+    .await;
+    deploy_ft(
+        &collateral_asset,
+        "Collateral Asset",
+        "COLLATERAL",
+        borrow_user.id(),
+        1000,
+    )
+    .await;
+    storage_deposit(&supply_user, borrow_asset.id(), contract.id()).await;
+    storage_deposit(&borrow_user, collateral_asset.id(), contract.id()).await;
+    storage_deposit(&supply_user, contract.id(), supply_user.id()).await;
+    storage_deposit(&borrow_user, contract.id(), borrow_user.id()).await;
+
+    ft_transfer_call(
+        &supply_user,
+        borrow_asset.id(),
+        contract.id(),
+        1000,
+        Nep141MarketDepositMessage::Supply,
+    )
+    .await;
+    ft_transfer_call(
+        &borrow_user,
+        collateral_asset.id(),
+        contract.id(),
+        500,
+        Nep141MarketDepositMessage::Collateralize,
+    )
+    .await;
+
+    // borrow_user is not registered with the borrow asset, so the transfer
+    // fails.
+    let result = borrow_user
+        .call(contract.id(), "borrow")
+        .args_json(json!({
+            "amount": U128(100),
+        }))
+        .max_gas()
+        .transact()
+        .await
+        .unwrap();
+
+    assert!(!result.receipt_failures().is_empty());
+
+    let borrow_position = contract
+        .view("get_borrow_position")
+        .args_json(json!({
+            "account_id": borrow_user.id(),
+        }))
+        .await
+        .unwrap()
+        .json::<Option<BorrowPosition>>()
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(borrow_position.borrow_asset_liability.0, 0);
+    assert_eq!(borrow_position.collateral_asset_deposit.0, 500);
+
+    let metrics = contract
+        .view("get_borrow_asset_metrics")
+        .args_json(json!({}))
+        .await
+        .unwrap()
+        .json::<BorrowAssetMetrics>()
+        .unwrap();
+
+    assert_eq!(metrics.used.0, 0);
+    assert_eq!(metrics.deposited.0, 1000);
+}
+
+#[tokio::test]
+async fn test_repay() {
+    let worker = near_workspaces::sandbox().await.unwrap();
+    accounts!(
+        worker,
+        owner_user,
+        supply_user,
+        borrow_user,
+        collateral_asset,
+        borrow_asset
+    );
+    let price_oracle = deploy_price_oracle(&worker).await;
+    let mut configuration = market_configuration(
+        borrow_asset.id().clone(),
+        collateral_asset.id().clone(),
+        owner_user.id().clone(),
+    );
+    configuration.price_oracle_configuration.account_id = price_oracle.id().clone();
+    let contract = setup_market(&worker, configuration).await;
+    set_price(&price_oracle, COLLATERAL_ASSET_PRICE_ID, 1, 0).await;
+    set_price(&price_oracle, BORROW_ASSET_PRICE_ID, 1, 0).await;
+    deploy_ft(
+        &borrow_asset,
+        "Borrow Asset",
+        "BORROW",
+        supply_user.id(),
+        2000,
+    )
+    .await;
+    deploy_ft(
+        &collateral_asset,
+        "Collateral Asset",
+        "COLLATERAL",
+        borrow_user.id(),
+        1000,
+    )
+    .await;
+    storage_deposit(&supply_user, borrow_asset.id(), contract.id()).await;
+    storage_deposit(&borrow_user, borrow_asset.id(), borrow_user.id()).await;
+    storage_deposit(&borrow_user, collateral_asset.id(), contract.id()).await;
+    storage_deposit(&supply_user, contract.id(), supply_user.id()).await;
+    storage_deposit(&borrow_user, contract.id(), borrow_user.id()).await;
+
+    ft_transfer_call(
+        &supply_user,
+        borrow_asset.id(),
+        contract.id(),
+        1000,
+        Nep141MarketDepositMessage::Supply,
+    )
+    .await;
+    ft_transfer_call(
+        &borrow_user,
+        collateral_asset.id(),
+        contract.id(),
+        500,
+        Nep141MarketDepositMessage::Collateralize,
+    )
+    .await;
+
+    borrow_user
+        .call(contract.id(), "borrow")
+        .args_json(json!({
+            "amount": U128(100),
+        }))
+        .max_gas()
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+
+    // The liability includes the 1% origination fee.
+    let borrow_position = contract
+        .view("get_borrow_position")
+        .args_json(json!({
+            "account_id": borrow_user.id(),
+        }))
+        .await
+        .unwrap()
+        .json::<Option<BorrowPosition>>()
+        .unwrap()
+        .unwrap();
+    assert_eq!(borrow_position.borrow_asset_liability.0, 101);
+
+    // supply_user repays part of borrow_user's liability.
+    ft_transfer_call(
+        &supply_user,
+        borrow_asset.id(),
+        contract.id(),
+        50,
+        Nep141MarketDepositMessage::RepayFor(RepayForMsg {
+            account_id: borrow_user.id().clone(),
+        }),
+    )
+    .await;
+
+    assert_eq!(ft_balance_of(&supply_user, borrow_asset.id()).await, 950);
+    let borrow_position = contract
+        .view("get_borrow_position")
+        .args_json(json!({
+            "account_id": borrow_user.id(),
+        }))
+        .await
+        .unwrap()
+        .json::<Option<BorrowPosition>>()
+        .unwrap()
+        .unwrap();
+    assert_eq!(borrow_position.borrow_asset_liability.0, 51);
+    let borrow_position = contract
+        .view("get_borrow_position")
+        .args_json(json!({
+            "account_id": supply_user.id(),
+        }))
+        .await
+        .unwrap()
+        .json::<Option<BorrowPosition>>()
+        .unwrap();
+    assert!(borrow_position.is_none());
+
+    // borrow_user sends more than the remaining liability, and the excess is
+    // refunded.
+    ft_transfer_call(
+        &borrow_user,
+        borrow_asset.id(),
+        contract.id(),
+        100,
+        Nep141MarketDepositMessage::Repay,
+    )
+    .await;
+
+    assert_eq!(ft_balance_of(&borrow_user, borrow_asset.id()).await, 49);
+    let borrow_position = contract
+        .view("get_borrow_position")
+        .args_json(json!({
+            "account_id": borrow_user.id(),
+        }))
+        .await
+        .unwrap()
+        .json::<Option<BorrowPosition>>()
+        .unwrap()
+        .unwrap();
+    assert_eq!(borrow_position.borrow_asset_liability.0, 0);
+    assert_eq!(borrow_position.collateral_asset_deposit.0, 500);
+}
+
+#[tokio::test]
+async fn test_partial_liquidation() {
+    let worker = near_workspaces::sandbox().await.unwrap();
+    accounts!(
+        worker,
+        supply_user,
+        borrow_user,
+        collateral_asset,
+        borrow_asset
+    );
+    // supply_user is also the liquidator.
+    let price_oracle = deploy_price_oracle(&worker).await;
+    let mut configuration = market_configuration(
+        borrow_asset.id().clone(),
+        collateral_asset.id().clone(),
+        supply_user.id().clone(),
+    );
+    configuration.price_oracle_configuration.account_id = price_oracle.id().clone();
+    // Only the liquidator and the protocol share the margin, so that the
+    // small margin below is not lost to rounding.
+    configuration.liquidation_spread = LiquidationSpread {
+        supply_position: 0.into(),
+        liquidator: 1.into(),
+        protocol: 1.into(),
+    };
+    let contract = setup_market(&worker, configuration).await;
+    set_price(&price_oracle, COLLATERAL_ASSET_PRICE_ID, 1, 0).await;
+    set_price(&price_oracle, BORROW_ASSET_PRICE_ID, 1, 0).await;
+    deploy_ft(
+        &borrow_asset,
+        "Borrow Asset",
+        "BORROW",
+        supply_user.id(),
+        2000,
+    )
+    .await;
+    deploy_ft(
+        &collateral_asset,
+        "Collateral Asset",
+        "COLLATERAL",
+        borrow_user.id(),
+        1000,
+    )
+    .await;
+    storage_deposit(&supply_user, borrow_asset.id(), contract.id()).await;
+    storage_deposit(&borrow_user, borrow_asset.id(), borrow_user.id()).await;
+    storage_deposit(&borrow_user, collateral_asset.id(), contract.id()).await;
+    storage_deposit(&supply_user, contract.id(), supply_user.id()).await;
+    storage_deposit(&borrow_user, contract.id(), borrow_user.id()).await;
+
+    ft_transfer_call(
+        &supply_user,
+        borrow_asset.id(),
+        contract.id(),
+        1000,
+        Nep141MarketDepositMessage::Supply,
+    )
+    .await;
+    ft_transfer_call(
+        &borrow_user,
+        collateral_asset.id(),
+        contract.id(),
+        500,
+        Nep141MarketDepositMessage::Collateralize,
+    )
+    .await;
+
+    borrow_user
+        .call(contract.id(), "borrow")
+        .args_json(json!({
+            "amount": U128(100),
+        }))
+        .max_gas()
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(ft_balance_of(&borrow_user, borrow_asset.id()).await, 100);
+
+    set_price(&price_oracle, COLLATERAL_ASSET_PRICE_ID, 2, -1).await;
+
+    let liquidate_msg = Nep141MarketDepositMessage::Liquidate(LiquidateMsg {
+        account_id: borrow_user.id().clone(),
+    });
+
+    // supply_user is not registered with the collateral asset, so the
+    // transfer of the collateral fails, and the liquidation is reverted.
+    ft_transfer_call(
+        &supply_user,
+        borrow_asset.id(),
+        contract.id(),
+        100,
+        &liquidate_msg,
+    )
+    .await;
+
+    assert_eq!(ft_balance_of(&supply_user, borrow_asset.id()).await, 1000);
+
+    let borrow_position = contract
+        .view("get_borrow_position")
+        .args_json(json!({
+            "account_id": borrow_user.id(),
+        }))
+        .await
+        .unwrap()
+        .json::<Option<BorrowPosition>>()
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(borrow_position.borrow_asset_liability.0, 101);
+    assert_eq!(borrow_position.collateral_asset_deposit.0, 500);
+
+    storage_deposit(&supply_user, collateral_asset.id(), supply_user.id()).await;
+
+    // The collateral is now worth 100, less than 120% of the liability of
+    // 101. Half of the liability, rounded up, can be repaid, for collateral
+    // worth 105% of it. The liquidator also pays the 5% bonus, rounded down,
+    // as the margin.
+    ft_transfer_call(
+        &supply_user,
+        borrow_asset.id(),
+        contract.id(),
+        100,
+        &liquidate_msg,
+    )
+    .await;
+
+    // The excess is refunded.
+    assert_eq!(ft_balance_of(&supply_user, borrow_asset.id()).await, 947);
+    assert_eq!(
+        ft_balance_of(&supply_user, collateral_asset.id()).await,
+        267
+    );
+
+    // The margin of 2 is split between the liquidator and the protocol.
+    let liquidator_rewards = contract
+        .view("get_liquidator_rewards")
+        .args_json(json!({
+            "account_id": supply_user.id(),
+        }))
+        .await
+        .unwrap()
+        .json::<U128>()
+        .unwrap();
+    assert_eq!(liquidator_rewards.0, 1);
+    let protocol_rewards = contract
+        .view("get_protocol_rewards")
+        .await
+        .unwrap()
+        .json::<U128>()
+        .unwrap();
+    assert_eq!(protocol_rewards.0, 1);
+
+    let borrow_position = contract
+        .view("get_borrow_position")
+        .args_json(json!({
+            "account_id": borrow_user.id(),
+        }))
+        .await
+        .unwrap()
+        .json::<Option<BorrowPosition>>()
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(borrow_position.borrow_asset_liability.0, 50);
+    assert_eq!(borrow_position.collateral_asset_deposit.0, 233);
+}
+
+#[tokio::test]
+async fn test_withdraw_collateral() {
+    let worker = near_workspaces::sandbox().await.unwrap();
+    accounts!(
+        worker,
+        owner_user,
+        supply_user,
+        borrow_user,
+        collateral_asset,
+        borrow_asset
+    );
+    let price_oracle = deploy_price_oracle(&worker).await;
+    let mut configuration = market_configuration(
+        borrow_asset.id().clone(),
+        collateral_asset.id().clone(),
+        owner_user.id().clone(),
+    );
+    configuration.price_oracle_configuration.account_id = price_oracle.id().clone();
+    let contract = setup_market(&worker, configuration).await;
+    set_price(&price_oracle, COLLATERAL_ASSET_PRICE_ID, 1, 0).await;
+    set_price(&price_oracle, BORROW_ASSET_PRICE_ID, 1, 0).await;
+    deploy_ft(
+        &borrow_asset,
+        "Borrow Asset",
+        "BORROW",
+        supply_user.id(),
+        1000,
+    )
+    .await;
+    deploy_ft(
+        &collateral_asset,
+        "Collateral Asset",
+        "COLLATERAL",
+        borrow_user.id(),
+        1000,
+    )
+    .await;
+    storage_deposit(&supply_user, borrow_asset.id(), contract.id()).await;
+    storage_deposit(&borrow_user, borrow_asset.id(), borrow_user.id()).await;
+    storage_deposit(&borrow_user, collateral_asset.id(), contract.id()).await;
+    storage_deposit(&supply_user, contract.id(), supply_user.id()).await;
+    storage_deposit(&borrow_user, contract.id(), borrow_user.id()).await;
+
+    ft_transfer_call(
+        &supply_user,
+        borrow_asset.id(),
+        contract.id(),
+        1000,
+        Nep141MarketDepositMessage::Supply,
+    )
+    .await;
+    ft_transfer_call(
+        &borrow_user,
+        collateral_asset.id(),
+        contract.id(),
+        600,
+        Nep141MarketDepositMessage::Collateralize,
+    )
+    .await;
+
+    // Without a liability, collateral can be withdrawn even while the price
+    // oracle is down.
+    price_oracle
+        .call("set_outage")
+        .args_json(json!({ "outage": true }))
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+    borrow_user
+        .call(contract.id(), "withdraw_collateral")
+        .args_json(json!({
+            "amount": U128(100),
+        }))
+        .max_gas()
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+    price_oracle
+        .call("set_outage")
+        .args_json(json!({ "outage": false }))
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(
+        ft_balance_of(&borrow_user, collateral_asset.id()).await,
+        500
+    );
+
+    borrow_user
+        .call(contract.id(), "borrow")
+        .args_json(json!({
+            "amount": U128(100),
+        }))
+        .max_gas()
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+
+    // 50 is less than 120% of the liability of 101.
+    let result = borrow_user
+        .call(contract.id(), "withdraw_collateral")
+        .args_json(json!({
+            "amount": U128(450),
+        }))
+        .max_gas()
+        .transact()
+        .await
+        .unwrap();
+
+    assert!(result.is_failure());
+    assert_eq!(
+        ft_balance_of(&borrow_user, collateral_asset.id()).await,
+        500
+    );
+
+    borrow_user
+        .call(contract.id(), "withdraw_collateral")
+        .args_json(json!({
+            "amount": U128(300),
+        }))
+        .max_gas()
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(
+        ft_balance_of(&borrow_user, collateral_asset.id()).await,
+        800
+    );
+
+    let borrow_position = contract
+        .view("get_borrow_position")
+        .args_json(json!({
+            "account_id": borrow_user.id(),
+        }))
+        .await
+        .unwrap()
+        .json::<Option<BorrowPosition>>()
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(borrow_position.collateral_asset_deposit.0, 200);
+}
+
+#[tokio::test]
+async fn test_borrow_limits() {
+    let worker = near_workspaces::sandbox().await.unwrap();
+    accounts!(
+        worker,
+        owner_user,
+        supply_user,
+        borrow_user,
+        collateral_asset,
+        borrow_asset
+    );
+    let price_oracle = deploy_price_oracle(&worker).await;
+    let mut configuration = market_configuration(
+        borrow_asset.id().clone(),
+        collateral_asset.id().clone(),
+        owner_user.id().clone(),
+    );
+    configuration.price_oracle_configuration.account_id = price_oracle.id().clone();
+    configuration.minimum_borrow_amount = 10.into();
+    configuration.maximum_borrow_amount = 300.into();
+    let contract = setup_market(&worker, configuration).await;
+    set_price(&price_oracle, COLLATERAL_ASSET_PRICE_ID, 1, 0).await;
+    set_price(&price_oracle, BORROW_ASSET_PRICE_ID, 1, 0).await;
+    deploy_ft(
+        &borrow_asset,
+        "Borrow Asset",
+        "BORROW",
+        supply_user.id(),
+        1000,
+    )
+    .await;
+    deploy_ft(
+        &collateral_asset,
+        "Collateral Asset",
+        "COLLATERAL",
+        borrow_user.id(),
+        1000,
+    )
+    .await;
+    storage_deposit(&supply_user, borrow_asset.id(), contract.id()).await;
+    storage_deposit(&borrow_user, borrow_asset.id(), borrow_user.id()).await;
+    storage_deposit(&borrow_user, collateral_asset.id(), contract.id()).await;
+    storage_deposit(&supply_user, contract.id(), supply_user.id()).await;
+    storage_deposit(&borrow_user, contract.id(), borrow_user.id()).await;
+
+    // 99% of the 200 supplied, 198, is available.
+    ft_transfer_call(
+        &supply_user,
+        borrow_asset.id(),
+        contract.id(),
+        200,
+        Nep141MarketDepositMessage::Supply,
+    )
+    .await;
+    ft_transfer_call(
+        &borrow_user,
+        collateral_asset.id(),
+        contract.id(),
+        1000,
+        Nep141MarketDepositMessage::Collateralize,
+    )
+    .await;
+
+    let borrow = |amount: u128| {
+        let contract = &contract;
+        let borrow_user = &borrow_user;
+        async move {
+            borrow_user
+                .call(contract.id(), "borrow")
+                .args_json(json!({
+                    "amount": U128(amount),
+                }))
+                .max_gas()
+                .transact()
+                .await
+                .unwrap()
+        }
+    };
+
+    assert_failure(
+        borrow(9).await,
+        "Borrow amount is less than the minimum borrow amount",
+    );
+    assert_failure(
+        borrow(301).await,
+        "Borrow amount is greater than the maximum borrow amount",
+    );
+    assert_failure(borrow(199).await, "Insufficient borrow asset available");
+
+    assert_eq!(ft_balance_of(&borrow_user, borrow_asset.id()).await, 0);
+
+    assert!(borrow(198).await.is_success());
+
+    assert_eq!(ft_balance_of(&borrow_user, borrow_asset.id()).await, 198);
+}
+
+#[tokio::test]
+async fn test_borrow_requires_valid_oracle_prices() {
+    let worker = near_workspaces::sandbox().await.unwrap();
+    accounts!(
+        worker,
+        owner_user,
+        supply_user,
+        borrow_user,
+        collateral_asset,
+        borrow_asset
+    );
+    let price_oracle = deploy_price_oracle(&worker).await;
+    let mut configuration = market_configuration(
+        borrow_asset.id().clone(),
+        collateral_asset.id().clone(),
+        owner_user.id().clone(),
+    );
+    configuration.price_oracle_configuration.account_id = price_oracle.id().clone();
+    let contract = setup_market(&worker, configuration).await;
+    deploy_ft(
+        &borrow_asset,
+        "Borrow Asset",
+        "BORROW",
+        supply_user.id(),
+        1000,
+    )
+    .await;
+    deploy_ft(
+        &collateral_asset,
+        "Collateral Asset",
+        "COLLATERAL",
+        borrow_user.id(),
+        1000,
+    )
+    .await;
+    storage_deposit(&supply_user, borrow_asset.id(), contract.id()).await;
+    storage_deposit(&borrow_user, borrow_asset.id(), borrow_user.id()).await;
+    storage_deposit(&borrow_user, collateral_asset.id(), contract.id()).await;
+    storage_deposit(&supply_user, contract.id(), supply_user.id()).await;
+    storage_deposit(&borrow_user, contract.id(), borrow_user.id()).await;
+
+    ft_transfer_call(
+        &supply_user,
+        borrow_asset.id(),
+        contract.id(),
+        1000,
+        Nep141MarketDepositMessage::Supply,
+    )
+    .await;
+    ft_transfer_call(
+        &borrow_user,
+        collateral_asset.id(),
+        contract.id(),
+        500,
+        Nep141MarketDepositMessage::Collateralize,
+    )
+    .await;
+
+    let borrow = || async {
+        borrow_user
+            .call(contract.id(), "borrow")
+            .args_json(json!({
+                "amount": U128(100),
+            }))
+            .max_gas()
+            .transact()
+            .await
+            .unwrap()
+    };
+
+    let reset_prices = || async {
+        set_price(&price_oracle, COLLATERAL_ASSET_PRICE_ID, 1, 0).await;
+        set_price(&price_oracle, BORROW_ASSET_PRICE_ID, 1, 0).await;
+    };
+
+    // Outage.
+    price_oracle
+        .call("set_outage")
+        .args_json(json!({ "outage": true }))
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(borrow().await.is_failure());
+    price_oracle
+        .call("set_outage")
+        .args_json(json!({ "outage": false }))
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+
+    // Missing price.
+    price_oracle
+        .call("remove_price")
+        .args_json(json!({ "price_id": BORROW_ASSET_PRICE_ID }))
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(borrow().await.is_failure());
+
+    // Stale price.
+    reset_prices().await;
+    price_oracle
+        .call("set_publish_time")
+        .args_json(json!({
+            "price_id": COLLATERAL_ASSET_PRICE_ID,
+            "publish_time": 0,
+        }))
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(borrow().await.is_failure());
+
+    // Confidence interval too wide.
+    reset_prices().await;
+    price_oracle
+        .call("set_confidence")
+        .args_json(json!({
+            "price_id": COLLATERAL_ASSET_PRICE_ID,
+            "conf": "1",
+        }))
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(borrow().await.is_failure());
+
+    assert_eq!(ft_balance_of(&borrow_user, borrow_asset.id()).await, 0);
+
+    reset_prices().await;
+    assert!(borrow().await.is_success());
+
+    assert_eq!(ft_balance_of(&borrow_user, borrow_asset.id()).await, 100);
+
+    let get_borrow_status = |collateral_asset_price| {
+        let contract = &contract;
+        let borrow_user = &borrow_user;
+        async move {
+            contract
+                .view("get_borrow_status")
+                .args_json(json!({
+                    "account_id": borrow_user.id(),
+                    "oracle_price_proof": OraclePriceProof {
+                        collateral_asset_price,
+                        borrow_asset_price: Rational::new(1, 1),
+                    },
+                }))
+                .await
+                .unwrap()
+                .json::<Option<BorrowStatus>>()
+                .unwrap()
+                .unwrap()
+        }
+    };
+
+    assert_eq!(
+        get_borrow_status(Rational::new(1, 1)).await,
+        BorrowStatus::Healthy,
+    );
+    assert_eq!(
+        get_borrow_status(Rational::new(1, 5)).await,
+        BorrowStatus::Liquidation,
+    );
+
+    let details = contract
+        .view("get_borrow_position_details")
+        .args_json(json!({
+            "account_id": borrow_user.id(),
+            "oracle_price_proof": OraclePriceProof {
+                collateral_asset_price: Rational::new(1, 1),
+                borrow_asset_price: Rational::new(1, 1),
+            },
+        }))
+        .await
+        .unwrap()
+        .json::<Option<BorrowPositionDetails>>()
+        .unwrap()
+        .unwrap();
+
+    // 100 borrowed + 1% origination fee.
+    assert_eq!(details.collateral_ratio, Some(Rational::new(500, 101)));
+    assert_eq!(details.status, BorrowStatus::Healthy);
+}
+
+#[tokio::test]
+async fn test_configuration_updates() {
+    let worker = near_workspaces::sandbox().await.unwrap();
+    accounts!(worker, new_owner, collateral_asset, borrow_asset);
+    let contract = setup_market(
+        &worker,
+        market_configuration(
+            borrow_asset.id().clone(),
+            collateral_asset.id().clone(),
+            new_owner.id().clone(),
+        ),
+    )
+    .await;
+
+    let get_configuration = || async {
+        contract
+            .view("get_configuration")
+            .await
+            .unwrap()
+            .json::<MarketConfiguration>()
+            .unwrap()
+    };
+
+    // Only the owner can update the configuration.
+    assert!(new_owner
+        .call(contract.id(), "set_borrow_amount_limits")
+        .args_json(json!({ "minimum": U128(10), "maximum": U128(100) }))
+        .transact()
+        .await
+        .unwrap()
+        .is_failure());
+
+    // Updates are validated.
+    assert!(contract
+        .call("set_borrow_amount_limits")
+        .args_json(json!({ "minimum": U128(100), "maximum": U128(10) }))
+        .transact()
+        .await
+        .unwrap()
+        .is_failure());
+    assert!(contract
+        .call("set_minimum_collateral_ratio_per_borrow")
+        .args_json(json!({ "ratio": Rational::<u16>::new(1, 2) }))
+        .transact()
+        .await
+        .unwrap()
+        .is_failure());
+    assert!(contract
+        .call("set_origination_fee")
+        .args_json(json!({ "fee": Fee::Proportional(Rational::new(2, 1)) }))
+        .transact()
+        .await
+        .unwrap()
+        .is_failure());
+    assert!(new_owner
+        .call(contract.id(), "set_liquidation_policy")
+        .args_json(json!({ "policy": LiquidationPolicy::Open }))
+        .transact()
+        .await
+        .unwrap()
+        .is_failure());
+
+    contract
+        .call("set_borrow_amount_limits")
+        .args_json(json!({ "minimum": U128(10), "maximum": U128(100) }))
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+    contract
+        .call("set_minimum_collateral_ratio_per_borrow")
+        .args_json(json!({ "ratio": Rational::<u16>::new(3, 2) }))
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+    contract
+        .call("set_liquidation_policy")
+        .args_json(json!({
+            "policy": LiquidationPolicy::Allowlist(vec![new_owner.id().clone()]),
+        }))
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+
+    let configuration = get_configuration().await;
+    assert_eq!(configuration.minimum_borrow_amount, U128(10));
+    assert_eq!(configuration.maximum_borrow_amount, U128(100));
+    assert_eq!(
+        configuration.minimum_collateral_ratio_per_borrow,
+        Rational::new(3, 2),
+    );
+    assert_eq!(
+        configuration.liquidation_policy,
+        LiquidationPolicy::Allowlist(vec![new_owner.id().clone()]),
+    );
+
+    // Ownership transfer.
+    contract
+        .call("own_propose_owner")
+        .args_json(json!({ "account_id": new_owner.id() }))
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+    new_owner
+        .call(contract.id(), "own_accept_owner")
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert!(contract
+        .call("set_borrow_amount_limits")
+        .args_json(json!({ "minimum": U128(1), "maximum": U128(1000) }))
+        .transact()
+        .await
+        .unwrap()
+        .is_failure());
+    new_owner
+        .call(contract.id(), "set_borrow_amount_limits")
+        .args_json(json!({ "minimum": U128(1), "maximum": U128(1000) }))
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(get_configuration().await.maximum_borrow_amount, U128(1000));
+
+    // Configurations can be checked before deployment.
+    let mut configuration = get_configuration().await;
+    let validate_configuration = |configuration: MarketConfiguration| {
+        let contract = &contract;
+        async move {
+            contract
+                .view("validate_configuration")
+                .args_json(json!({ "configuration": configuration }))
+                .await
+                .unwrap()
+                .json::<Option<MarketConfigurationError>>()
+                .unwrap()
+        }
+    };
+    assert_eq!(validate_configuration(configuration.clone()).await, None);
+    configuration.collateral_asset = configuration.borrow_asset.clone();
+    assert_eq!(
+        validate_configuration(configuration).await,
+        Some(MarketConfigurationError::SameBorrowAndCollateralAsset),
+    );
+}
+
+#[tokio::test]
+async fn test_pause() {
+    let worker = near_workspaces::sandbox().await.unwrap();
+    accounts!(
+        worker,
+        guardian_user,
+        supply_user,
+        collateral_asset,
+        borrow_asset
+    );
+    let contract = setup_market(
+        &worker,
+        market_configuration(
+            borrow_asset.id().clone(),
+            collateral_asset.id().clone(),
+            guardian_user.id().clone(),
+        ),
+    )
+    .await;
+    deploy_ft(
+        &borrow_asset,
+        "Borrow Asset",
+        "BORROW",
+        supply_user.id(),
+        1000,
+    )
+    .await;
+    storage_deposit(&supply_user, borrow_asset.id(), contract.id()).await;
+    storage_deposit(&supply_user, contract.id(), supply_user.id()).await;
+
+    // Only the owner or a guardian can pause.
+    assert!(guardian_user
+        .call(contract.id(), "pause")
+        .args_json(json!({}))
+        .transact()
+        .await
+        .unwrap()
+        .is_failure());
+
+    contract
+        .call("add_guardian")
+        .args_json(json!({ "account_id": guardian_user.id() }))
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+    guardian_user
+        .call(contract.id(), "pause")
+        .args_json(json!({}))
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+
+    let paused = contract
+        .view("get_paused_actions")
+        .await
+        .unwrap()
+        .json::<PausedActions>()
+        .unwrap();
+    assert!(paused.supply && paused.withdraw);
+    assert!(paused.withdraw_collateral && paused.withdraw_rewards && paused.transfer_shares);
+    assert!(!paused.repay && !paused.liquidate);
+
+    // Paused deposits are refunded in full.
+    ft_transfer_call(
+        &supply_user,
+        borrow_asset.id(),
+        contract.id(),
+        1000,
+        Nep141MarketDepositMessage::Supply,
+    )
+    .await;
+    assert_eq!(ft_balance_of(&supply_user, borrow_asset.id()).await, 1000);
+
+    guardian_user
+        .call(contract.id(), "unpause")
+        .args_json(json!({ "actions": [MarketAction::Supply] }))
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+
+    ft_transfer_call(
+        &supply_user,
+        borrow_asset.id(),
+        contract.id(),
+        1000,
+        Nep141MarketDepositMessage::Supply,
+    )
+    .await;
+    assert_eq!(ft_balance_of(&supply_user, borrow_asset.id()).await, 0);
+
+    supply_user
+        .call(contract.id(), "queue_withdrawal")
+        .args_json(json!({ "amount": U128(600) }))
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+
+    // Withdrawal processing is still paused.
+    assert!(supply_user
+        .call(contract.id(), "process_next_withdrawal")
+        .args_json(json!({}))
+        .max_gas()
+        .transact()
+        .await
+        .unwrap()
+        .is_failure());
+
+    // So are share transfers.
+    storage_deposit(&guardian_user, contract.id(), guardian_user.id()).await;
+    assert_failure(
+        supply_user
+            .call(contract.id(), "ft_transfer")
+            .args_json(json!({
+                "receiver_id": guardian_user.id(),
+                "amount": U128(100),
+            }))
+            .deposit(NearToken::from_yoctonear(1))
+            .transact()
+            .await
+            .unwrap(),
+        "This action is paused",
+    );
+
+    contract
+        .call("unpause")
+        .args_json(json!({}))
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+    supply_user
+        .call(contract.id(), "process_next_withdrawal")
+        .args_json(json!({}))
+        .max_gas()
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(ft_balance_of(&supply_user, borrow_asset.id()).await, 600);
+}
+
+#[tokio::test]
+async fn test_native_deposits() {
+    let worker = near_workspaces::sandbox().await.unwrap();
+    accounts!(worker, owner_user, supply_user, collateral_asset);
+    let mut configuration = market_configuration(
+        owner_user.id().clone(),
+        collateral_asset.id().clone(),
+        owner_user.id().clone(),
+    );
+    configuration.borrow_asset = FungibleAsset::Native;
+    let contract = setup_market(&worker, configuration).await;
+
+    let amount = NearToken::from_near(5);
+
+    storage_deposit(&supply_user, contract.id(), supply_user.id()).await;
+
+    supply_user
+        .call(contract.id(), "supply_native")
+        .deposit(amount)
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+
+    let supply_position = contract
+        .view("get_supply_position")
+        .args_json(json!({
+            "account_id": supply_user.id(),
+        }))
+        .await
+        .unwrap()
+        .json::<Option<SupplyPosition>>()
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(supply_position.shares.0, amount.as_yoctonear());
+
+    // The collateral asset is not native NEAR.
+    assert!(supply_user
+        .call(contract.id(), "collateralize_native")
+        .deposit(amount)
+        .transact()
+        .await
+        .unwrap()
+        .is_failure());
+
+    // Nothing to repay, so the whole deposit is refunded.
+    let refund = supply_user
+        .call(contract.id(), "repay_native")
+        .deposit(amount)
+        .transact()
+        .await
+        .unwrap()
+        .json::<U128>()
+        .unwrap();
+
+    assert_eq!(refund.0, amount.as_yoctonear());
+}
+
+#[tokio::test]
+async fn test_storage_management() {
+    let worker = near_workspaces::sandbox().await.unwrap();
+    accounts!(worker, owner_user, supply_user, collateral_asset);
+    let mut configuration = market_configuration(
+        owner_user.id().clone(),
+        collateral_asset.id().clone(),
+        owner_user.id().clone(),
+    );
+    configuration.borrow_asset = FungibleAsset::Native;
+    let contract = setup_market(&worker, configuration).await;
+
+    let amount = NearToken::from_near(1);
+    let supply = || async {
+        supply_user
+            .call(contract.id(), "supply_native")
+            .deposit(amount)
+            .transact()
+            .await
+            .unwrap()
+    };
+    let storage_balance_of = || async {
+        contract
+            .view("storage_balance_of")
+            .args_json(json!({
+                "account_id": supply_user.id(),
+            }))
+            .await
+            .unwrap()
+            .json::<Option<StorageBalance>>()
+            .unwrap()
+    };
+    let storage_unregister = || async {
+        supply_user
+            .call(contract.id(), "storage_unregister")
+            .args_json(json!({}))
+            .deposit(NearToken::from_yoctonear(1))
+            .transact()
+            .await
+            .unwrap()
+    };
+
+    // Positions cannot be opened before registering.
+    assert!(supply().await.is_failure());
+    assert!(storage_balance_of().await.is_none());
+
+    let bounds = contract
+        .view("storage_balance_bounds")
+        .await
+        .unwrap()
+        .json::<StorageBalanceBounds>()
+        .unwrap();
+
+    assert!(!bounds.min.is_zero());
+
+    supply_user
+        .call(contract.id(), "storage_deposit")
+        .args_json(json!({
+            "registration_only": true,
+        }))
+        .deposit(NearToken::from_near(1))
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+
+    // Only the balance above the minimum is available for withdrawal.
+    let storage_balance = storage_balance_of().await.unwrap();
+    assert_eq!(storage_balance.total, bounds.min);
+    assert!(storage_balance.available.is_zero());
+
+    supply().await.unwrap();
+
+    // The supply position is still open.
+    assert!(storage_unregister().await.is_failure());
+    assert_failure(
+        supply_user
+            .call(contract.id(), "storage_unregister")
+            .args_json(json!({ "force": true }))
+            .deposit(NearToken::from_yoctonear(1))
+            .transact()
+            .await
+            .unwrap(),
+        "Cannot force unregister an account with open positions",
+    );
+
+    supply_user
+        .call(contract.id(), "queue_withdrawal")
+        .args_json(json!({
+            "amount": U128(amount.as_yoctonear()),
+        }))
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+
+    owner_user
+        .call(contract.id(), "process_next_withdrawal")
+        .args_json(json!({}))
+        .max_gas()
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert!(storage_unregister().await.json::<bool>().unwrap());
+    assert!(storage_balance_of().await.is_none());
+
+    let supply_position = contract
+        .view("get_supply_position")
+        .args_json(json!({
+            "account_id": supply_user.id(),
+        }))
+        .await
+        .unwrap()
+        .json::<Option<SupplyPosition>>()
+        .unwrap();
+
+    assert!(supply_position.is_none());
+}